@@ -1,10 +1,12 @@
 use core::fmt;
+use std::{cell::RefCell, mem, rc::Rc};
 
 use crate::{
-    expr::Expr,
-    lexer::{Loc, Token, TokenKind},
+    expr::{Expr, ExprKind, OperandType, OperatorKind},
+    lexer::{Loc, Span, Token, TokenKind},
 };
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LogLevel {
     Info,
     Warning,
@@ -20,95 +22,160 @@ impl fmt::Display for LogLevel {
         write!(f, "{}", out)
     }
 }
-pub struct Diagnoster {}
-
+// an error that can be reported by a Diagnoster
+pub trait Problem: fmt::Display {
+    // source ranges to underline when reporting the error
+    fn problem_spans(&self) -> Vec<Span>;
+}
+// a reported problem, as handed to a DiagSink
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub level: LogLevel,
+    pub message: String,
+    pub spans: Vec<Span>,
+}
+// destination of reported problems
+pub trait DiagSink {
+    fn emit(&self, diagnostic: Diagnostic);
+}
+// prints problems to stderr, underlining the source they come from
+pub struct StderrSink;
+impl DiagSink for StderrSink {
+    fn emit(&self, diagnostic: Diagnostic) {
+        eprintln!("{}:  {}", diagnostic.level, diagnostic.message);
+        for span in &diagnostic.spans {
+            eprint!("{}", format_problem_area(span));
+        }
+    }
+}
+// keeps problems around, for embedders and tests that want to inspect them
+#[derive(Default)]
+pub struct CollectSink {
+    diagnostics: RefCell<Vec<Diagnostic>>,
+}
+impl CollectSink {
+    pub fn take(&self) -> Vec<Diagnostic> {
+        mem::take(&mut self.diagnostics.borrow_mut())
+    }
+}
+impl DiagSink for CollectSink {
+    fn emit(&self, diagnostic: Diagnostic) {
+        self.diagnostics.borrow_mut().push(diagnostic);
+    }
+}
+// handle to a sink, clones report to the same sink so the lexer, parser and evaluation
+// environment of a session can share one
+#[derive(Clone)]
+pub struct Diagnoster {
+    sink: Rc<dyn DiagSink>,
+}
 impl Diagnoster {
-    pub fn report(&self, error: ParserError) {
-        eprintln!("{}:  {}", LogLevel::Error, &error.to_string());
-        self.report_problem_area(error);
+    pub fn new(sink: Rc<dyn DiagSink>) -> Self {
+        Diagnoster { sink }
     }
-    fn report_problem_area(&self, error: ParserError) {
-        let problem_loc = match error {
-            ParserError::UnexpectedChar { char: _, loc } => loc,
-            ParserError::ExpectedToken {
-                expected: _,
-                found: _,
-                while_doing: _,
-                loc,
-            } => loc,
-            ParserError::UnexpectedToken {
-                found,
-                while_doing: _,
-            } => found.loc,
-            ParserError::InvalidExpr {
-                loc,
-                found: _,
+    pub fn stderr() -> Self {
+        Diagnoster::new(Rc::new(StderrSink))
+    }
+    pub fn report(&self, error: &impl Problem) {
+        self.sink.emit(Diagnostic {
+            level: LogLevel::Error,
+            message: error.to_string(),
+            spans: error.problem_spans(),
+        });
+    }
+}
+pub fn format_problem_area(span: &Span) -> String {
+    let underline = format!(
+        "{}{}",
+        " ".repeat(span.start.col_idx()),
+        "^".repeat(span.width())
+    );
+    match &span.start {
+        Loc::Repl { line, .. } => {
+            format!("    {}\n    {}\n", line, underline)
+        }
+        loc @ Loc::File { line, .. } => {
+            format!("  --> {}\n    {}\n    {}\n", loc, line, underline)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LexError {
+    UnexpectedChar {
+        char: char,
+        span: Span,
+    },
+    InvalidNumLit {
+        literal: String,
+        reason: String,
+        span: Span,
+    },
+    UnterminatedComment {
+        span: Span,
+    },
+    UnreadableInput {
+        reason: String,
+        span: Span,
+    },
+}
+impl Problem for LexError {
+    fn problem_spans(&self) -> Vec<Span> {
+        match self {
+            LexError::UnexpectedChar { char: _, span } => vec![span.clone()],
+            LexError::InvalidNumLit {
+                literal: _,
                 reason: _,
-            } => loc,
-            ParserError::UnusedParams {
-                functor: _,
-                func_def: _,
-                unused_params: _,
-            } => {
-                eprintln!("TODO: report UnusedParams");
-                return;
-            }
-            ParserError::InvalidFuncParam {
-                found: _,
-                while_doing: _,
-            } => {
-                eprintln!("TODO: report invalid function arguments");
-                return;
-            }
-            ParserError::RecusiveFuncDef { func_def: _ } => {
-                eprintln!("TODO: report recursive function definition");
-                return;
+                span,
+            } => vec![span.clone()],
+            LexError::UnterminatedComment { span } => vec![span.clone()],
+            LexError::UnreadableInput { reason: _, span } => vec![span.clone()],
+        }
+    }
+}
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let out = match self {
+            LexError::UnexpectedChar { char, span: _ } => &format!("Found unexpected '{}'.", char),
+            LexError::InvalidNumLit {
+                literal,
+                reason,
+                span: _,
+            } => &format!("Invalid numeric literal '{}': {}.", literal, reason),
+            LexError::UnterminatedComment { span: _ } => {
+                "Found block comment without a closing '*/'."
             }
-            ParserError::IncompleteFuncDef {
-                func_def: _,
-                undefined_var: _,
-            } => {
-                eprintln!("TODO: report incomplete function definition");
-                return;
+            LexError::UnreadableInput { reason, span: _ } => {
+                &format!("Failed to read the rest of the input: {}.", reason)
             }
         };
-        match problem_loc {
-            Loc::Repl { line, idx } => {
-                let idx = idx as usize;
-                let mut buffer = String::from(" ".repeat(line.len()));
-                buffer.insert(idx, '^');
-                eprintln!("    {}", line);
-                eprintln!("    {}", buffer)
-            }
-            Loc::File { ln: _, col: _ } => todo!(),
-        }
+        write!(f, "{}", out)
     }
 }
 
+#[derive(Debug)]
 pub enum ParserError {
-    UnexpectedChar {
-        char: char,
-        loc: Loc,
-    },
+    // a lexical error in the statement being parsed
+    Lex(LexError),
     ExpectedToken {
         expected: Vec<TokenKind>,
-        found: Option<Token>,
+        found: Option<Token<'static>>,
         while_doing: String,
-        loc: Loc,
+        span: Span,
     },
     UnexpectedToken {
-        found: Token,
+        found: Token<'static>,
         while_doing: String,
     },
     InvalidExpr {
-        loc: Loc,
+        span: Span,
         found: Box<Expr>,
         reason: String,
     },
     UnusedParams {
         functor: Box<Expr>,
         func_def: Box<Expr>,
-        unused_params: Vec<String>,
+        unused_params: Vec<Expr>,
     },
     InvalidFuncParam {
         found: Box<Expr>,
@@ -116,13 +183,64 @@ pub enum ParserError {
     },
     RecusiveFuncDef {
         func_def: Box<Expr>,
+        recursive_call: Box<Expr>,
     },
     IncompleteFuncDef {
         func_def: Box<Expr>,
-        undefined_var: String,
+        undefined_var: Box<Expr>,
+    },
+    UnreachableClause {
+        clause: Box<Expr>,
+        catch_all: Box<Expr>,
     },
 }
-fn pretty_enumerate<T: std::fmt::Display>(items: &Vec<T>) -> String {
+impl Problem for ParserError {
+    fn problem_spans(&self) -> Vec<Span> {
+        match self {
+            ParserError::Lex(error) => error.problem_spans(),
+            ParserError::ExpectedToken {
+                expected: _,
+                found: _,
+                while_doing: _,
+                span,
+            } => vec![span.clone()],
+            ParserError::UnexpectedToken {
+                found,
+                while_doing: _,
+            } => vec![found.span.clone()],
+            ParserError::InvalidExpr {
+                span,
+                found: _,
+                reason: _,
+            } => vec![span.clone()],
+            ParserError::UnusedParams {
+                functor: _,
+                func_def: _,
+                unused_params,
+            } => unused_params
+                .iter()
+                .filter_map(|param| param.span.clone())
+                .collect(),
+            ParserError::InvalidFuncParam {
+                found,
+                while_doing: _,
+            } => found.span.iter().cloned().collect(),
+            ParserError::RecusiveFuncDef {
+                func_def: _,
+                recursive_call,
+            } => recursive_call.span.iter().cloned().collect(),
+            ParserError::IncompleteFuncDef {
+                func_def: _,
+                undefined_var,
+            } => undefined_var.span.iter().cloned().collect(),
+            ParserError::UnreachableClause {
+                clause,
+                catch_all: _,
+            } => clause.span.iter().cloned().collect(),
+        }
+    }
+}
+fn pretty_enumerate<T: std::fmt::Display>(items: &[T]) -> String {
     let mut out_msg = String::new();
     for (i, kind) in items.iter().enumerate() {
         out_msg.push_str(&kind.to_string());
@@ -139,14 +257,12 @@ fn pretty_enumerate<T: std::fmt::Display>(items: &Vec<T>) -> String {
 impl fmt::Display for ParserError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let out = match self {
-            ParserError::UnexpectedChar { char, loc: _ } => {
-                &format!("Found unexpected '{}'.", char)
-            }
+            ParserError::Lex(error) => &error.to_string(),
             ParserError::ExpectedToken {
                 expected,
                 found,
                 while_doing,
-                loc: _,
+                span: _,
             } => {
                 let mut expected_msg = String::new();
                 if expected.len() > 1 {
@@ -166,7 +282,7 @@ impl fmt::Display for ParserError {
                 &format!("Found unexpected {} {}.", found, while_doing)
             }
             ParserError::InvalidExpr {
-                loc: _,
+                span: _,
                 found,
                 reason,
             } => &format!(
@@ -187,15 +303,276 @@ impl fmt::Display for ParserError {
                 "Found {}, which is not a valid parameter, while {}.",
                 found, while_doing
             ),
-            ParserError::RecusiveFuncDef { func_def } => &format!(
-                "Found function definition {}, which is recursive..",
+            ParserError::RecusiveFuncDef {
+                func_def,
+                recursive_call: _,
+            } => &format!(
+                "Found function definition {}, which calls itself outside of a conditional branch, so it can never stop.",
                 func_def
             ),
             ParserError::IncompleteFuncDef { func_def, undefined_var } => &format!(
                 "Found variable {},which is neither defined in locals or a function parameter, in function definition {}.",
                 undefined_var,func_def
             ),
+            ParserError::UnreachableClause { clause, catch_all } => &format!(
+                "Found clause {}, which can never apply because the earlier clause {} matches every call.",
+                clause, catch_all
+            ),
         };
         write!(f, "{}", out)
     }
 }
+
+pub enum EvalError {
+    InvalidOperand {
+        op_kind: OperatorKind,
+        expected: OperandType,
+        operand: Box<Expr>,
+    },
+    MismatchedOperands {
+        op_kind: OperatorKind,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    InvalidCondition {
+        cond: Box<Expr>,
+    },
+    NoMatchingBranch {
+        expr: Box<Expr>,
+    },
+    NotAFunction {
+        call: Box<Expr>,
+        callee: Box<Expr>,
+    },
+    WrongArgCount {
+        call: Box<Expr>,
+        expected: usize,
+        found: usize,
+    },
+    LengthMismatch {
+        op_kind: OperatorKind,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    InvalidIndex {
+        target: Box<Expr>,
+        index: Box<Expr>,
+    },
+    InvalidRange {
+        range: Box<Expr>,
+    },
+    DimensionMismatch {
+        call: Box<Expr>,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    NotSquare {
+        call: Box<Expr>,
+        matrix: Box<Expr>,
+    },
+    SingularMatrix {
+        call: Box<Expr>,
+        matrix: Box<Expr>,
+    },
+    NonIntegerPower {
+        call: Box<Expr>,
+    },
+    InvalidDimension {
+        call: Box<Expr>,
+    },
+    RecursionLimit {
+        call: Box<Expr>,
+        limit: usize,
+    },
+    NoMatchingClause {
+        call: Box<Expr>,
+        clauses: Vec<Expr>,
+    },
+    UndefinedArity {
+        call: Box<Expr>,
+        arities: Vec<String>,
+    },
+    UnknownNamedArg {
+        call: Box<Expr>,
+        arg: Box<Expr>,
+    },
+    MissingArg {
+        call: Box<Expr>,
+        param: Box<Expr>,
+    },
+    NoValues {
+        call: Box<Expr>,
+    },
+}
+impl Problem for EvalError {
+    fn problem_spans(&self) -> Vec<Span> {
+        match self {
+            EvalError::InvalidOperand {
+                op_kind: _,
+                expected: _,
+                operand,
+            } => operand.span.iter().cloned().collect(),
+            EvalError::MismatchedOperands {
+                op_kind: _,
+                left,
+                right,
+            } => left.span.iter().chain(right.span.iter()).cloned().collect(),
+            EvalError::InvalidCondition { cond } => cond.span.iter().cloned().collect(),
+            EvalError::NoMatchingBranch { expr } => expr.span.iter().cloned().collect(),
+            EvalError::NotAFunction { call, callee: _ } => call.span.iter().cloned().collect(),
+            EvalError::WrongArgCount { call, .. } => call.span.iter().cloned().collect(),
+            EvalError::LengthMismatch {
+                op_kind: _,
+                left,
+                right,
+            } => left.span.iter().chain(right.span.iter()).cloned().collect(),
+            EvalError::InvalidIndex { target: _, index } => index.span.iter().cloned().collect(),
+            EvalError::InvalidRange { range } => range.span.iter().cloned().collect(),
+            EvalError::DimensionMismatch { call, .. }
+            | EvalError::NotSquare { call, .. }
+            | EvalError::SingularMatrix { call, .. }
+            | EvalError::NonIntegerPower { call }
+            | EvalError::InvalidDimension { call }
+            | EvalError::RecursionLimit { call, .. }
+            | EvalError::NoMatchingClause { call, .. }
+            | EvalError::UndefinedArity { call, .. }
+            | EvalError::MissingArg { call, .. }
+            | EvalError::NoValues { call } => call.span.iter().cloned().collect(),
+            EvalError::UnknownNamedArg { call: _, arg } => arg.span.iter().cloned().collect(),
+        }
+    }
+}
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::InvalidOperand {
+                op_kind,
+                expected,
+                operand,
+            } => write!(
+                f,
+                "Operator {} expects {} operands, found {}.",
+                op_kind, expected, operand
+            ),
+            EvalError::MismatchedOperands {
+                op_kind,
+                left,
+                right,
+            } => write!(
+                f,
+                "Operator {} expects operands of the same type, found {} and {}.",
+                op_kind, left, right
+            ),
+            EvalError::InvalidCondition { cond } => {
+                write!(f, "Expected a boolean condition, found {}.", cond)
+            }
+            EvalError::NoMatchingBranch { expr } => {
+                write!(f, "None of the conditions of {} hold.", expr)
+            }
+            EvalError::NotAFunction { call, callee } => {
+                write!(
+                    f,
+                    "Can't call {} in {}, it is not a function.",
+                    callee, call
+                )
+            }
+            EvalError::WrongArgCount {
+                call,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Call {} passes {} arguments to a function taking {}.",
+                call, found, expected
+            ),
+            EvalError::LengthMismatch {
+                op_kind,
+                left,
+                right,
+            } => write!(
+                f,
+                "Operator {} expects lists of the same length, found {} and {}.",
+                op_kind, left, right
+            ),
+            EvalError::InvalidIndex { target, index } => {
+                write!(f, "Can't index {} with {}.", target, index)
+            }
+            EvalError::InvalidRange { range } => {
+                write!(f, "Range {} is too large to evaluate.", range)
+            }
+            EvalError::DimensionMismatch { call, left, right } => write!(
+                f,
+                "Dimensions of {} and {} don't match in {}.",
+                left, right, call
+            ),
+            EvalError::NotSquare { call, matrix } => {
+                write!(f, "{} needs a square matrix, found {}.", call, matrix)
+            }
+            EvalError::SingularMatrix { call, matrix } => write!(
+                f,
+                "{} needs an invertible matrix, {} is singular.",
+                call, matrix
+            ),
+            EvalError::NonIntegerPower { call } => {
+                write!(
+                    f,
+                    "Matrices can only be raised to integer powers in {}.",
+                    call
+                )
+            }
+            EvalError::InvalidDimension { call } => {
+                write!(f, "Matrix sizes must be positive integers in {}.", call)
+            }
+            EvalError::RecursionLimit { call, limit } => write!(
+                f,
+                "Call {} nests more than {} calls deep, is there a base case?",
+                call, limit
+            ),
+            EvalError::NoMatchingClause { call, clauses } => {
+                let clauses: Vec<String> = clauses.iter().map(Expr::to_string).collect();
+                write!(
+                    f,
+                    "No clause matches {}, tried {}.",
+                    call,
+                    clauses.join(" and ")
+                )
+            }
+            EvalError::UndefinedArity { call, arities } => {
+                let ExprKind::Fun { name, params } = &call.kind else {
+                    panic!("{} is not a call", call)
+                };
+                write!(
+                    f,
+                    "Call {} passes {} arguments, but {} is only defined for {}.",
+                    call,
+                    params.len(),
+                    name,
+                    pretty_enumerate(arities)
+                )
+            }
+            EvalError::UnknownNamedArg { call, arg } => {
+                let ExprKind::Fun { name, .. } = &call.kind else {
+                    panic!("{} is not a call", call)
+                };
+                write!(
+                    f,
+                    "Call {} passes {} by name, but {} has no parameter of that name.",
+                    call, arg, name
+                )
+            }
+            EvalError::MissingArg { call, param } => {
+                let ExprKind::Fun { name, .. } = &call.kind else {
+                    panic!("{} is not a call", call)
+                };
+                write!(
+                    f,
+                    "Call {} passes no value for parameter {} of {}, which has no default.",
+                    call, param, name
+                )
+            }
+            EvalError::NoValues { call } => {
+                write!(f, "Call {} has no values to take the mean of.", call)
+            }
+        }
+    }
+}
@@ -1,38 +1,133 @@
-use std::{collections::HashMap, fmt, iter::zip};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    iter::zip,
+    rc::Rc,
+    slice,
+};
 
-use crate::{diag::Diagnoster, lexer::TokenKind};
+use crate::{
+    diag::{Diagnoster, EvalError, ParserError},
+    lexer::{Span, TokenKind},
+};
+
+mod clause;
+mod matrix;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OperatorKind {
     Equals,
     DoubleEquals,
+    NotEquals,
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
+    And,
+    Or,
+    Not,
     Mult,
     Div,
     Plus,
     Min,
     Pow,
+    Range,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandType {
+    Numeric,
+    Bool,
+}
+impl fmt::Display for OperandType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let output = match self {
+            OperandType::Numeric => "numeric",
+            OperandType::Bool => "boolean",
+        };
+        write!(f, "{}", output)
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Associativity {
+    Left,
+    Right,
 }
 impl OperatorKind {
     pub fn get_precedence(self) -> i32 {
         match self {
+            OperatorKind::Not => 0,
             OperatorKind::Pow => 0,
             OperatorKind::Mult => 1,
             OperatorKind::Div => 1,
             OperatorKind::Plus => 2,
             OperatorKind::Min => 2,
-            OperatorKind::Equals => 3,
-            OperatorKind::DoubleEquals => 3,
+            OperatorKind::Range => 3,
+            OperatorKind::Less => 4,
+            OperatorKind::LessEquals => 4,
+            OperatorKind::Greater => 4,
+            OperatorKind::GreaterEquals => 4,
+            OperatorKind::DoubleEquals => 5,
+            OperatorKind::NotEquals => 5,
+            OperatorKind::And => 6,
+            OperatorKind::Or => 7,
+            OperatorKind::Equals => 8,
+        }
+    }
+    pub fn associativity(self) -> Associativity {
+        match self {
+            OperatorKind::Pow => Associativity::Right,
+            _ => Associativity::Left,
+        }
+    }
+    // left and right binding power used by the parser, higher binds tighter. the precedence
+    // levels above run from 0 to 8, each level gets two powers to break ties by associativity
+    pub fn binding_power(self) -> (u8, u8) {
+        let power = 2 * (9 - self.get_precedence()) as u8;
+        match self.associativity() {
+            Associativity::Left => (power, power + 1),
+            Associativity::Right => (power + 1, power),
+        }
+    }
+    // whether the operator applies element by element to lists
+    pub fn broadcasts(self) -> bool {
+        self.operand_type().is_some() && self != OperatorKind::Range
+    }
+    // type the operands must have, None if the operator accepts any type
+    pub fn operand_type(self) -> Option<OperandType> {
+        match self {
+            OperatorKind::Mult
+            | OperatorKind::Div
+            | OperatorKind::Plus
+            | OperatorKind::Min
+            | OperatorKind::Pow
+            | OperatorKind::Range
+            | OperatorKind::Less
+            | OperatorKind::LessEquals
+            | OperatorKind::Greater
+            | OperatorKind::GreaterEquals => Some(OperandType::Numeric),
+            OperatorKind::And | OperatorKind::Or | OperatorKind::Not => Some(OperandType::Bool),
+            OperatorKind::Equals | OperatorKind::DoubleEquals | OperatorKind::NotEquals => None,
         }
     }
     pub fn from_token_kind(kind: &TokenKind) -> Self {
         match kind {
             TokenKind::Equals => Self::Equals,
             TokenKind::DoubleEquals => Self::DoubleEquals,
+            TokenKind::NotEquals => Self::NotEquals,
+            TokenKind::Less => Self::Less,
+            TokenKind::LessEquals => Self::LessEquals,
+            TokenKind::Greater => Self::Greater,
+            TokenKind::GreaterEquals => Self::GreaterEquals,
+            TokenKind::And => Self::And,
+            TokenKind::Or => Self::Or,
+            TokenKind::Not => Self::Not,
             TokenKind::Mult => Self::Mult,
             TokenKind::Div => Self::Div,
             TokenKind::Plus => Self::Plus,
             TokenKind::Min => Self::Min,
             TokenKind::Pow => Self::Pow,
+            TokenKind::Superscript => Self::Pow,
+            TokenKind::DotDot => Self::Range,
             _ => panic!("called OperatorKind::fromt_token_kind on a {:?}", kind),
         }
     }
@@ -47,32 +142,157 @@ impl fmt::Display for OperatorKind {
             OperatorKind::Pow => "^",
             OperatorKind::Equals => "=",
             OperatorKind::DoubleEquals => "==",
+            OperatorKind::NotEquals => "!=",
+            OperatorKind::Less => "<",
+            OperatorKind::LessEquals => "<=",
+            OperatorKind::Greater => ">",
+            OperatorKind::GreaterEquals => ">=",
+            OperatorKind::And => "&&",
+            OperatorKind::Or => "||",
+            OperatorKind::Not => "!",
+            OperatorKind::Range => "..",
         };
         write!(f, "{}", output)
     }
 }
 
+// ranges with more elements are reported instead of filling up memory
+const MAX_RANGE_LEN: f64 = 10_000_000.0;
+
+// names that evaluate to a fixed value unless shadowed by a variable
+pub fn constant_value(name: &str) -> Option<f64> {
+    match name {
+        "π" | "pi" => Some(std::f64::consts::PI),
+        _ => None,
+    }
+}
+
+// stack a nested call may take, in debug builds a call of a piecewise function takes about 50 KiB
+pub const STACK_PER_CALL: usize = 64 * 1024;
+// calls can nest this deep before evaluation gives up, which keeps recursion within the 2 MiB
+// stack of a spawned thread even in debug builds, the repl reserves stack for MAX_DEPTH instead
+pub const DEFAULT_MAX_DEPTH: usize = 30;
+// limit of the repl and highest one it accepts, it runs on a thread with stack for that many calls
+pub const MAX_DEPTH: usize = 1000;
+
+#[derive(Clone)]
 pub struct EvalEnv {
-    pub vars: HashMap<String, Box<Expr>>,
-    pub funcs: HashMap<String, Box<Expr>>,
+    // shared with the environments of calls, so these need not copy them
+    pub vars: Rc<HashMap<String, Box<Expr>>>,
+    // the variables of the environment functions are defined in, which function bodies refer
+    // to. None if that is vars itself
+    globals: Option<Rc<HashMap<String, Box<Expr>>>>,
+    // the clauses name(patterns) = body defining each function for each number of arguments,
+    // tried in order
+    pub funcs: Rc<HashMap<String, BTreeMap<usize, Vec<Expr>>>>,
     pub diag: Diagnoster,
+    // most calls that may be nested, so runaway recursion is reported instead of overflowing
+    pub max_depth: usize,
+    // calls currently being evaluated
+    depth: usize,
 }
 impl EvalEnv {
     pub fn new() -> Self {
+        EvalEnv::with_diag(Diagnoster::stderr())
+    }
+    // copy of the variables, except those that are shadowed by names
+    pub fn without_vars(&self, names: &[String]) -> EvalEnv {
+        let mut result = EvalEnv::with_diag(self.diag.clone());
+        result.vars = Rc::new(
+            self.vars
+                .iter()
+                .filter(|(name, _)| !names.contains(name))
+                .map(|(name, val)| (name.clone(), val.clone()))
+                .collect(),
+        );
+        result
+    }
+    // copy of the environment with name bound to val, shadowing any function of that name.
+    // functions called from here do not see name
+    pub fn with_var(&self, name: &str, val: Expr) -> EvalEnv {
+        let mut result = self.clone();
+        if result.funcs.contains_key(name) {
+            Rc::make_mut(&mut result.funcs).remove(name);
+        }
+        result.globals = Some(self.globals().clone());
+        Rc::make_mut(&mut result.vars).insert(name.to_string(), Box::new(val));
+        result
+    }
+    // the value of the variable name, a parameter of the current call or a global
+    pub fn var(&self, name: &str) -> Option<&Expr> {
+        match self.vars.get(name) {
+            Some(val) => Some(val),
+            None => self.globals.as_ref()?.get(name).map(|val| val.as_ref()),
+        }
+    }
+    fn globals(&self) -> &Rc<HashMap<String, Box<Expr>>> {
+        self.globals.as_ref().unwrap_or(&self.vars)
+    }
+    // adds the clause name(patterns) = body, replacing an earlier clause with the same patterns.
+    // clauses taking another number of arguments overload the function
+    pub fn define(&mut self, clause: Expr) {
+        let (name, patterns, _) = clause.clause_parts();
+        let clauses = Rc::make_mut(&mut self.funcs)
+            .entry(name.to_string())
+            .or_default()
+            .entry(patterns.len())
+            .or_default();
+        match clauses
+            .iter_mut()
+            .find(|earlier| earlier.same_patterns(&clause))
+        {
+            Some(earlier) => *earlier = clause,
+            None => clauses.push(clause),
+        }
+    }
+    // the clauses of name taking arity arguments
+    pub fn clauses(&self, name: &str, arity: usize) -> Option<&Vec<Expr>> {
+        self.funcs.get(name)?.get(&arity)
+    }
+    // the numbers of arguments name can be called with, such as 2, 1 to 3 or 0 or more
+    pub fn arities(&self, name: &str) -> Vec<String> {
+        let mut arities = vec![];
+        for clause in self
+            .funcs
+            .get(name)
+            .into_iter()
+            .flat_map(BTreeMap::values)
+            .flatten()
+        {
+            let arity = match clause.arity_range() {
+                (min, Some(max)) if min == max => min.to_string(),
+                (min, Some(max)) => format!("{} to {}", min, max),
+                (min, None) => format!("{} or more", min),
+            };
+            if !arities.contains(&arity) {
+                arities.push(arity);
+            }
+        }
+        arities
+    }
+    // empty environment reporting to diag
+    pub fn with_diag(diag: Diagnoster) -> Self {
         EvalEnv {
-            vars: HashMap::new(),
-            funcs: HashMap::new(),
-            diag: Diagnoster {},
+            vars: Rc::new(HashMap::new()),
+            globals: None,
+            funcs: Rc::new(HashMap::new()),
+            diag,
+            max_depth: DEFAULT_MAX_DEPTH,
+            depth: 0,
         }
     }
 }
 #[derive(Debug, Clone, PartialEq)]
-pub enum Expr {
+pub enum ExprKind {
     BinOp {
         op_kind: OperatorKind,
         left: Box<Expr>,
         right: Box<Expr>,
     },
+    UnaryOp {
+        op_kind: OperatorKind,
+        operand: Box<Expr>,
+    },
     Fun {
         name: String,
         params: Vec<Expr>,
@@ -81,34 +301,103 @@ pub enum Expr {
     Variable(String),
     Group(Box<Expr>),
     Bool(bool),
+    // [a, b, c]
+    List(Vec<Expr>),
+    // (a, b), a fixed number of values that are not broadcast over
+    Tuple(Vec<Expr>),
+    // [[a, b], [c, d]], stored as rows of equal length
+    Matrix(Vec<Vec<Expr>>),
+    // target[index], the first element has index 0
+    Index {
+        target: Box<Expr>,
+        index: Box<Expr>,
+    },
+    // params -> body, a function as a value
+    Lambda {
+        params: Vec<String>,
+        body: Box<Expr>,
+    },
+    // name..., the last parameter of a definition, bound to a list of the remaining arguments
+    Variadic(Box<Expr>),
+    // let name = value in body, name is only bound inside body
+    Let {
+        name: String,
+        value: Box<Expr>,
+        body: Box<Expr>,
+    },
+    // if cond then then else otherwise
+    If {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+    },
+    // (condition, value) branches tried in order, otherwise applies when no condition holds
+    Piecewise {
+        branches: Vec<(Expr, Expr)>,
+        otherwise: Option<Box<Expr>>,
+    },
+}
+// an expression node together with the source it was parsed from,
+// expressions produced during evaluation keep the span of the node they replace
+#[derive(Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Option<Span>,
+}
+impl From<ExprKind> for Expr {
+    fn from(kind: ExprKind) -> Self {
+        Expr { kind, span: None }
+    }
+}
+impl PartialEq for Expr {
+    // spans are only used for diagnostics and are ignored when comparing expressions
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)
+    }
 }
 impl Expr {
-    pub fn eval(&self, eval_env: &mut EvalEnv) -> Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr {
+            kind,
+            span: Some(span),
+        }
+    }
+    fn spanned(&self, kind: ExprKind) -> Self {
+        Expr {
+            kind,
+            span: self.span.clone(),
+        }
+    }
+    pub fn eval(&self, eval_env: &mut EvalEnv) -> Option<Expr> {
         // top-level entrypoint for evaluation, can insert variable declarations etc
         // this calls eval_recursive for further (non-mutable eval_env) evaluation
-        match self {
-            Expr::BinOp {
+        // errors are reported through eval_env.diag, in which case None is returned
+        match &self.kind {
+            ExprKind::BinOp {
                 op_kind,
                 left,
                 right,
             } => {
                 if *op_kind == OperatorKind::Equals {
                     let mut right = right.clone();
-                    match *left.clone() {
-                        Expr::Fun { name, params: _ } => {
-                            eval_env.funcs.insert(name, Box::new(self.clone()));
-                        }
-                        Expr::Variable(name) => {
-                            right = Box::new(right.eval_recursive(eval_env));
-                            eval_env.vars.insert(name, right.clone());
+                    match &left.kind {
+                        ExprKind::Fun { .. } => eval_env.define(self.capture_defaults(eval_env)),
+                        ExprKind::Variable(name) => {
+                            *right = right.eval_recursive(eval_env)?;
+                            Rc::make_mut(&mut eval_env.vars).insert(name.clone(), right.clone());
                         }
                         _ => panic!("Invalid expression, should not have been parsed"),
                     };
-                    Expr::BinOp {
+                    Some(self.spanned(ExprKind::BinOp {
                         op_kind: *op_kind,
                         left: left.clone(),
-                        right: right.clone(),
-                    }
+                        right,
+                    }))
                 } else {
                     self.eval_recursive(eval_env)
                 }
@@ -116,242 +405,995 @@ impl Expr {
             _ => self.eval_recursive(eval_env),
         }
     }
-    fn eval_recursive(&self, eval_env: &EvalEnv) -> Expr {
+    fn eval_recursive(&self, eval_env: &EvalEnv) -> Option<Expr> {
         // evaluates expressions without evaluating equalities, therefore does not need a mut eval_env
-        match self {
-            Expr::BinOp {
+        match &self.kind {
+            ExprKind::BinOp {
                 op_kind,
                 left,
                 right,
-            } => {
-                let left = left.eval_recursive(eval_env);
-                let right = right.eval_recursive(eval_env);
-                if left.is_num() && right.is_num() {
-                    // evaluate pure numerical expressions
-                    let a = left.expect_val("expect val on is_num==true");
-                    let b = right.expect_val("expect val on is_num==true");
-                    return match op_kind {
-                        //TODO:  maybe overloading addition etc for Expr to simplify?
-                        OperatorKind::Mult => Expr::Numeric(a * b),
-                        OperatorKind::Div => Expr::Numeric(a / b),
-                        OperatorKind::Plus => Expr::Numeric(a + b),
-                        OperatorKind::Min => Expr::Numeric(a - b),
-                        OperatorKind::Pow => Expr::Numeric(a.powf(b)),
-                        OperatorKind::Equals => Expr::BinOp {
-                            op_kind: *op_kind,
-                            left: Box::new(left.eval_recursive(eval_env)),
-                            right: Box::new(right.eval_recursive(eval_env)),
-                        },
-                        OperatorKind::DoubleEquals => {
-                            // TODO: decide what to do for symbolic evaluations?
-                            // would like to be able to ascertain that f(a,b)==f(a,b) is true
-                            let left = left.eval_recursive(eval_env);
-                            let right = right.eval_recursive(eval_env);
-                            if left == right {
-                                return Expr::Bool(true);
-                            } else {
-                                return Expr::Bool(false);
-                            }
-                        }
-                    };
+            } => self.eval_binop(op_kind, left, right, eval_env),
+            ExprKind::UnaryOp { op_kind, operand } => self.eval_unary(op_kind, operand, eval_env),
+            ExprKind::Fun {
+                name: eval_name,
+                params: eval_args,
+            } => self.eval_fun(eval_name, eval_args, eval_env),
+            ExprKind::Numeric(_) => Some(self.clone()),
+            ExprKind::Variable(name) => {
+                if let Some(val) = eval_env.var(name) {
+                    Some(self.spanned(val.kind.clone()))
+                } else if let Some(lambda) = eval_env.funcs.get(name).and_then(as_lambda) {
+                    // a named function passed around as a value
+                    Some(self.spanned(lambda))
+                } else if let Some(val) = constant_value(name) {
+                    Some(self.spanned(ExprKind::Numeric(val)))
+                } else {
+                    Some(self.clone())
                 }
-                if left.is_bool() && right.is_bool() {
-                    // evaluate pure boolean expressions
-                    let left = left.expect_bool("expected bool on is_bool=true");
-                    let right = right.expect_bool("expected bool on is_bool=true");
-                    return match op_kind {
-                        OperatorKind::DoubleEquals => Expr::Bool(left == right),
-                        OperatorKind::Mult => Expr::Bool(left && right),
-                        OperatorKind::Equals => todo!(),
-                        OperatorKind::Div => todo!(),
-                        OperatorKind::Plus => Expr::Bool(left || right),
-                        OperatorKind::Min => todo!(),
-                        OperatorKind::Pow => todo!(),
-                    };
+            }
+            ExprKind::Group(expr) => {
+                let expr = expr.eval_recursive(eval_env)?;
+                // brackets already delimit lists and tuples
+                if expr.is_num() || expr.is_bool() || expr.is_var() || expr.is_collection() {
+                    Some(self.spanned(expr.kind))
+                } else {
+                    Some(self.spanned(ExprKind::Group(Box::new(expr))))
                 }
-                let mut right = right;
-                let mut op_kind = op_kind;
-                if right.is_num() {
-                    // simplification step, maybe better to factor out with other simplifications?
-                    if right.expect_val("expected val on is_num==true") < 0.0 {
-                        match op_kind {
-                            OperatorKind::Plus => {
-                                op_kind = &OperatorKind::Min;
-                                right = Expr::Numeric(
-                                    -right.expect_val("expected val on is_num==true"),
-                                );
-                            }
-                            OperatorKind::Min => {
-                                op_kind = &OperatorKind::Plus;
-                                right = Expr::Numeric(
-                                    -right.expect_val("expected val on is_num==true"),
-                                );
-                            }
-                            _ => (),
-                        }
+            }
+            ExprKind::Bool(_) => Some(self.clone()),
+            ExprKind::List(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| item.eval_recursive(eval_env))
+                    .collect::<Option<Vec<Expr>>>()?;
+                Some(self.spanned(matrix::list_or_matrix(items)))
+            }
+            ExprKind::Tuple(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| item.eval_recursive(eval_env))
+                    .collect::<Option<Vec<Expr>>>()?;
+                Some(self.spanned(ExprKind::Tuple(items)))
+            }
+            ExprKind::Matrix(rows) => {
+                let rows = rows
+                    .iter()
+                    .map(|row| {
+                        row.iter()
+                            .map(|entry| entry.eval_recursive(eval_env))
+                            .collect()
+                    })
+                    .collect::<Option<Vec<Vec<Expr>>>>()?;
+                Some(self.spanned(ExprKind::Matrix(rows)))
+            }
+            ExprKind::Index { target, index } => self.eval_index(target, index, eval_env),
+            ExprKind::Let { name, value, body } => {
+                let value = value.eval_recursive(eval_env)?;
+                // a symbolic value is grouped, so it keeps its meaning wherever name is used
+                let value = match value.is_value() || value.is_var() {
+                    true => value,
+                    false => value.spanned(ExprKind::Group(Box::new(value.clone()))),
+                };
+                body.eval_recursive(&eval_env.with_var(name, value))
+            }
+            ExprKind::Lambda { params, body } => {
+                // the variables the body refers to are captured now, its parameters are left for
+                // when it is called
+                let captured = eval_env.without_vars(params);
+                Some(self.spanned(ExprKind::Lambda {
+                    params: params.clone(),
+                    body: Box::new(body.substitute(&captured)),
+                }))
+            }
+            ExprKind::If {
+                cond,
+                then,
+                otherwise,
+            } => self.eval_if(cond, then, otherwise, eval_env),
+            ExprKind::Piecewise {
+                branches,
+                otherwise,
+            } => self.eval_piecewise(branches, otherwise, eval_env),
+            ExprKind::Variadic(_) => unreachable!("only parsed as a parameter of a definition"),
+        }
+    }
+    fn eval_binop(
+        &self,
+        op_kind: &OperatorKind,
+        left: &Expr,
+        right: &Expr,
+        eval_env: &EvalEnv,
+    ) -> Option<Expr> {
+        let left = left.eval_recursive(eval_env)?;
+        let right = right.eval_recursive(eval_env)?;
+        if let Some(operand_type) = op_kind.operand_type() {
+            for operand in [&left, &right] {
+                check_operand_type(*op_kind, operand_type, operand, eval_env)?;
+            }
+        }
+        if left.is_matrix() || right.is_matrix() {
+            return self.eval_matrix_binop(*op_kind, left, right, eval_env);
+        }
+        if op_kind.broadcasts() && (left.is_list() || right.is_list()) {
+            return self.broadcast(*op_kind, left, right, eval_env);
+        }
+        if left.is_num() && right.is_num() {
+            // evaluate pure numerical expressions
+            let a = left.expect_val("expect val on is_num==true");
+            let b = right.expect_val("expect val on is_num==true");
+            return Some(match op_kind {
+                //TODO:  maybe overloading addition etc for Expr to simplify?
+                OperatorKind::Mult => self.spanned(ExprKind::Numeric(a * b)),
+                OperatorKind::Div => self.spanned(ExprKind::Numeric(a / b)),
+                OperatorKind::Plus => self.spanned(ExprKind::Numeric(a + b)),
+                OperatorKind::Min => self.spanned(ExprKind::Numeric(a - b)),
+                OperatorKind::Pow => self.spanned(ExprKind::Numeric(a.powf(b))),
+                OperatorKind::Less => self.spanned(ExprKind::Bool(a < b)),
+                OperatorKind::LessEquals => self.spanned(ExprKind::Bool(a <= b)),
+                OperatorKind::Greater => self.spanned(ExprKind::Bool(a > b)),
+                OperatorKind::GreaterEquals => self.spanned(ExprKind::Bool(a >= b)),
+                OperatorKind::Equals => self.spanned(ExprKind::BinOp {
+                    op_kind: *op_kind,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                }),
+                // TODO: decide what to do for symbolic evaluations?
+                // would like to be able to ascertain that f(a,b)==f(a,b) is true
+                OperatorKind::DoubleEquals => self.spanned(ExprKind::Bool(left == right)),
+                OperatorKind::NotEquals => self.spanned(ExprKind::Bool(left != right)),
+                OperatorKind::Range => {
+                    // both ends are included, stepping by one from the start
+                    let len = if b >= a { (b - a).floor() + 1.0 } else { 0.0 };
+                    if !len.is_finite() || len > MAX_RANGE_LEN {
+                        eval_env.diag.report(&EvalError::InvalidRange {
+                            range: Box::new(self.clone()),
+                        });
+                        return None;
                     }
+                    let items = (0..len as usize)
+                        .map(|i| self.spanned(ExprKind::Numeric(a + i as f64)))
+                        .collect();
+                    self.spanned(ExprKind::List(items))
+                }
+                OperatorKind::And | OperatorKind::Or | OperatorKind::Not => {
+                    unreachable!("operand types are checked above")
                 }
-                Expr::BinOp {
+            });
+        }
+        if matches!(
+            op_kind,
+            OperatorKind::DoubleEquals | OperatorKind::NotEquals
+        ) && left.is_collection()
+            && right.is_collection()
+            && left.is_value()
+            && right.is_value()
+        {
+            // lists and tuples are equal when all their elements are
+            let equal = left == right;
+            return Some(self.spanned(ExprKind::Bool(
+                equal == (*op_kind == OperatorKind::DoubleEquals),
+            )));
+        }
+        if left.is_bool() && right.is_bool() {
+            // evaluate pure boolean expressions
+            let a = left.expect_bool("expected bool on is_bool=true");
+            let b = right.expect_bool("expected bool on is_bool=true");
+            return Some(match op_kind {
+                OperatorKind::DoubleEquals => self.spanned(ExprKind::Bool(a == b)),
+                OperatorKind::NotEquals => self.spanned(ExprKind::Bool(a != b)),
+                OperatorKind::And => self.spanned(ExprKind::Bool(a && b)),
+                OperatorKind::Or => self.spanned(ExprKind::Bool(a || b)),
+                OperatorKind::Equals => self.spanned(ExprKind::BinOp {
                     op_kind: *op_kind,
                     left: Box::new(left),
                     right: Box::new(right),
+                }),
+                _ => unreachable!("operand types are checked above"),
+            });
+        }
+        if (left.is_num() && right.is_bool()) || (left.is_bool() && right.is_num()) {
+            // only (in)equality accepts both types, and they must match
+            eval_env.diag.report(&EvalError::MismatchedOperands {
+                op_kind: *op_kind,
+                left: Box::new(left),
+                right: Box::new(right),
+            });
+            return None;
+        }
+        let mut right = right;
+        let mut op_kind = op_kind;
+        if right.is_num() {
+            // simplification step, maybe better to factor out with other simplifications?
+            if right.expect_val("expected val on is_num==true") < 0.0 {
+                match op_kind {
+                    OperatorKind::Plus => {
+                        op_kind = &OperatorKind::Min;
+                        right = right.spanned(ExprKind::Numeric(
+                            -right.expect_val("expected val on is_num==true"),
+                        ));
+                    }
+                    OperatorKind::Min => {
+                        op_kind = &OperatorKind::Plus;
+                        right = right.spanned(ExprKind::Numeric(
+                            -right.expect_val("expected val on is_num==true"),
+                        ));
+                    }
+                    _ => (),
                 }
             }
-            Expr::Fun {
-                name: eval_name,
-                params: eval_args,
-            } => {
-                if let Some(val) = eval_env.funcs.get(eval_name) {
-                    match *val.clone() {
-                        Expr::BinOp {
-                            op_kind: _,
-                            left,
-                            right,
-                        } => {
-                            if let Expr::Fun {
-                                name: _,
-                                params: args,
-                            } = *left.clone()
-                            {
-                                // TODO: Find a more convenient way to save functions and evaluate them
-                                if args.len() == eval_args.len() {
-                                    let mut temp_eval_env = EvalEnv::new();
-                                    for (arg_name, arg_value) in zip(args, eval_args) {
-                                        let arg_name = arg_name
-                                            .expect_name("function argument not a variable");
-                                        temp_eval_env
-                                            .vars
-                                            .insert(arg_name.clone(), Box::new(arg_value.clone()));
-                                    }
-
-                                    let mut right = right.eval_recursive(&temp_eval_env);
-                                    if !right.is_num() {
-                                        right = right.eval_recursive(eval_env);
-                                    }
-                                    if right.is_num() {
-                                        return right;
-                                    } else {
-                                        return Expr::BinOp {
-                                            op_kind: OperatorKind::Equals,
-                                            left: left,
-                                            right: Box::new(right),
-                                        };
-                                    };
-                                }
-                            }
-                            todo!("not sure how to handle this");
-                        }
-
-                        _ => panic!("didn't find BinOp in stashed function definition"),
+        }
+        Some(self.spanned(ExprKind::BinOp {
+            op_kind: *op_kind,
+            left: Box::new(left),
+            right: Box::new(right),
+        }))
+    }
+    fn eval_unary(
+        &self,
+        op_kind: &OperatorKind,
+        operand: &Expr,
+        eval_env: &EvalEnv,
+    ) -> Option<Expr> {
+        let operand = operand.eval_recursive(eval_env)?;
+        let apply = |item: &Expr| {
+            self.spanned(ExprKind::UnaryOp {
+                op_kind: *op_kind,
+                operand: Box::new(item.clone()),
+            })
+            .eval_recursive(eval_env)
+        };
+        match &operand.kind {
+            ExprKind::List(items) => {
+                let items = items.iter().map(apply).collect::<Option<Vec<Expr>>>()?;
+                return Some(self.spanned(ExprKind::List(items)));
+            }
+            ExprKind::Matrix(rows) => {
+                let rows = rows
+                    .iter()
+                    .map(|row| row.iter().map(apply).collect())
+                    .collect::<Option<Vec<Vec<Expr>>>>()?;
+                return Some(self.spanned(ExprKind::Matrix(rows)));
+            }
+            _ => (),
+        }
+        if let Some(operand_type) = op_kind.operand_type() {
+            check_operand_type(*op_kind, operand_type, &operand, eval_env)?;
+        }
+        Some(match op_kind {
+            OperatorKind::Not if operand.is_bool() => self.spanned(ExprKind::Bool(
+                !operand.expect_bool("expected bool on is_bool=true"),
+            )),
+            OperatorKind::Min if operand.is_num() => self.spanned(ExprKind::Numeric(
+                -operand.expect_val("expected value on is_num=true"),
+            )),
+            // unary plus does nothing besides checking its operand is numeric
+            OperatorKind::Plus => self.spanned(operand.kind),
+            _ => self.spanned(ExprKind::UnaryOp {
+                op_kind: *op_kind,
+                operand: Box::new(operand),
+            }),
+        })
+    }
+    fn eval_fun(&self, eval_name: &str, eval_args: &[Expr], eval_env: &EvalEnv) -> Option<Expr> {
+        // arguments are evaluated where the call is, before being bound to parameters. those
+        // passed by name keep their name
+        let args = eval_args
+            .iter()
+            .map(|arg| match &arg.kind {
+                ExprKind::BinOp {
+                    op_kind: OperatorKind::Equals,
+                    left,
+                    right,
+                } => Some(arg.spanned(ExprKind::BinOp {
+                    op_kind: OperatorKind::Equals,
+                    left: left.clone(),
+                    right: Box::new(right.eval_recursive(eval_env)?),
+                })),
+                _ => arg.eval_recursive(eval_env),
+            })
+            .collect::<Option<Vec<Expr>>>()?;
+        let call = self.spanned(ExprKind::Fun {
+            name: eval_name.to_string(),
+            params: args.clone(),
+        });
+        if let Some(arities) = eval_env.funcs.get(eval_name) {
+            // clauses taking as many arguments as are passed come first, then those filling in
+            // defaults or collecting the remaining arguments
+            let exact = arities.get(&args.len()).into_iter().flatten();
+            let others = arities
+                .iter()
+                .filter(|(arity, _)| **arity != args.len())
+                .flat_map(|(_, clauses)| clauses);
+            let candidates: Vec<(&Expr, Vec<Option<Expr>>)> = exact
+                .chain(others)
+                .filter_map(|clause| {
+                    let (_, params, _) = clause.clause_parts();
+                    Some((clause, clause::bind_args(params, &args)?))
+                })
+                .collect();
+            for (clause, slots) in &candidates {
+                let (_, patterns, body) = clause.clause_parts();
+                let clause_args = self.fill_defaults(patterns, slots.clone(), eval_env)?;
+                let mut bound = vec![];
+                match clause::match_all(patterns, &clause_args, &mut bound) {
+                    clause::Match::Fails => continue,
+                    // which clause applies depends on the value of a symbol
+                    clause::Match::Unknown => return Some(call),
+                    clause::Match::Matches => (),
+                }
+                let (params, args): (Vec<String>, Vec<Expr>) = bound.into_iter().unzip();
+                let right = eval_call(self, body, &params, &args, eval_env)?;
+                if right.is_value() {
+                    return Some(self.spanned(right.kind));
+                }
+                let ExprKind::BinOp { left, .. } = &clause.kind else {
+                    unreachable!("clause_parts checks for a definition")
+                };
+                return Some(self.spanned(ExprKind::BinOp {
+                    op_kind: OperatorKind::Equals,
+                    left: left.clone(),
+                    right: Box::new(right),
+                }));
+            }
+            // clauses can add to or overload a builtin, which handles the calls they don't match
+            let result = self.eval_builtin(eval_name, &args, eval_env)?;
+            if result != call {
+                return Some(result);
+            }
+            let params = arities
+                .values()
+                .flatten()
+                .flat_map(|clause| clause.clause_parts().1);
+            let unknown_name = args.iter().find(|arg| {
+                arg.named_arg().is_some_and(|(name, _)| {
+                    !params.clone().any(|param| param.param_name() == Some(name))
+                })
+            });
+            match (unknown_name, candidates.is_empty()) {
+                (Some(arg), _) => eval_env.diag.report(&EvalError::UnknownNamedArg {
+                    call: Box::new(call.clone()),
+                    arg: Box::new(arg.clone()),
+                }),
+                (None, true) => {
+                    // a call passing a fitting number of arguments can still leave out one
+                    let missing = arities
+                        .values()
+                        .flatten()
+                        .filter(|clause| match clause.arity_range() {
+                            (min, Some(max)) => (min..=max).contains(&args.len()),
+                            (min, None) => min <= args.len(),
+                        })
+                        .find_map(|clause| clause::missing_param(clause.clause_parts().1, &args));
+                    match missing {
+                        Some(param) => eval_env.diag.report(&EvalError::MissingArg {
+                            call: Box::new(call),
+                            param: Box::new(param.clone()),
+                        }),
+                        None => eval_env.diag.report(&EvalError::UndefinedArity {
+                            call: Box::new(call),
+                            arities: eval_env.arities(eval_name),
+                        }),
                     }
-                } else {
-                    self.clone()
                 }
+                (None, false) => eval_env.diag.report(&EvalError::NoMatchingClause {
+                    call: Box::new(call),
+                    clauses: candidates
+                        .into_iter()
+                        .map(|(clause, _)| clause.clone())
+                        .collect(),
+                }),
             }
-            Expr::Numeric(_) => self.clone(),
-            Expr::Variable(name) => {
-                if let Some(val) = eval_env.vars.get(name) {
-                    *val.clone()
-                } else {
-                    self.clone()
+            return None;
+        }
+        if let Some(val) = eval_env.var(eval_name) {
+            return self.call_value(val, &args, eval_env);
+        }
+        self.eval_builtin(eval_name, &args, eval_env)
+    }
+    fn eval_index(&self, target: &Expr, index: &Expr, eval_env: &EvalEnv) -> Option<Expr> {
+        let target = target.eval_recursive(eval_env)?;
+        let index = index.eval_recursive(eval_env)?;
+        match (&target.kind, &index.kind) {
+            (ExprKind::List(items) | ExprKind::Tuple(items), ExprKind::Numeric(i))
+                if i.fract() == 0.0 && *i >= 0.0 && (*i as usize) < items.len() =>
+            {
+                Some(self.spanned(items[*i as usize].kind.clone()))
+            }
+            // indexing a matrix gives a row
+            (ExprKind::Matrix(rows), ExprKind::Numeric(i))
+                if i.fract() == 0.0 && *i >= 0.0 && (*i as usize) < rows.len() =>
+            {
+                Some(self.spanned(ExprKind::List(rows[*i as usize].clone())))
+            }
+            // the element can't be known yet
+            (ExprKind::List(_) | ExprKind::Tuple(_) | ExprKind::Matrix(_), _)
+                if !index.is_value() =>
+            {
+                Some(self.spanned(ExprKind::Index {
+                    target: Box::new(target),
+                    index: Box::new(index),
+                }))
+            }
+            _ if !target.is_value() => Some(self.spanned(ExprKind::Index {
+                target: Box::new(target),
+                index: Box::new(index),
+            })),
+            _ => {
+                eval_env.diag.report(&EvalError::InvalidIndex {
+                    target: Box::new(target),
+                    index: Box::new(index),
+                });
+                None
+            }
+        }
+    }
+    fn eval_if(
+        &self,
+        cond: &Expr,
+        then: &Expr,
+        otherwise: &Expr,
+        eval_env: &EvalEnv,
+    ) -> Option<Expr> {
+        let cond = cond.eval_recursive(eval_env)?;
+        let branch = match eval_condition(&cond, eval_env)? {
+            Some(true) => then,
+            Some(false) => otherwise,
+            // neither branch is taken yet, but they are kept with variables substituted
+            // so the expression still means the same outside of eval_env
+            None => {
+                return Some(self.spanned(ExprKind::If {
+                    cond: Box::new(cond),
+                    then: Box::new(then.substitute(eval_env)),
+                    otherwise: Box::new(otherwise.substitute(eval_env)),
+                }))
+            }
+        };
+        Some(self.spanned(branch.eval_recursive(eval_env)?.kind))
+    }
+    fn eval_piecewise(
+        &self,
+        branches: &[(Expr, Expr)],
+        otherwise: &Option<Box<Expr>>,
+        eval_env: &EvalEnv,
+    ) -> Option<Expr> {
+        for (i, (cond, value)) in branches.iter().enumerate() {
+            let cond = cond.eval_recursive(eval_env)?;
+            match eval_condition(&cond, eval_env)? {
+                Some(true) => {
+                    return Some(self.spanned(value.eval_recursive(eval_env)?.kind));
+                }
+                Some(false) => (),
+                None => {
+                    // keep this branch and the ones after it, like a symbolic if
+                    let mut branches_left = vec![(cond, value.substitute(eval_env))];
+                    for (cond, value) in &branches[i + 1..] {
+                        branches_left.push((cond.substitute(eval_env), value.substitute(eval_env)));
+                    }
+                    return Some(
+                        self.spanned(ExprKind::Piecewise {
+                            branches: branches_left,
+                            otherwise: otherwise
+                                .as_ref()
+                                .map(|otherwise| Box::new(otherwise.substitute(eval_env))),
+                        }),
+                    );
                 }
             }
-            Expr::Group(expr) => {
-                let expr = expr.eval_recursive(eval_env);
-                if expr.is_num() || expr.is_bool() || expr.is_var() {
-                    expr
-                } else {
-                    Expr::Group(Box::new(expr))
+        }
+        match otherwise {
+            Some(otherwise) => Some(self.spanned(otherwise.eval_recursive(eval_env)?.kind)),
+            None => {
+                eval_env.diag.report(&EvalError::NoMatchingBranch {
+                    expr: Box::new(self.clone()),
+                });
+                None
+            }
+        }
+    }
+    // copy with the variables bound in eval_env replaced by their values, without evaluating
+    // anything, for branches that may never be taken
+    fn substitute(&self, eval_env: &EvalEnv) -> Expr {
+        let sub = |expr: &Expr| Box::new(expr.substitute(eval_env));
+        let kind = match &self.kind {
+            ExprKind::BinOp {
+                op_kind,
+                left,
+                right,
+            } => ExprKind::BinOp {
+                op_kind: *op_kind,
+                left: sub(left),
+                right: sub(right),
+            },
+            ExprKind::UnaryOp { op_kind, operand } => ExprKind::UnaryOp {
+                op_kind: *op_kind,
+                operand: sub(operand),
+            },
+            ExprKind::Fun { name, params } => ExprKind::Fun {
+                name: name.clone(),
+                params: params
+                    .iter()
+                    .map(|param| param.substitute(eval_env))
+                    .collect(),
+            },
+            ExprKind::Variable(name) => match eval_env.vars.get(name) {
+                Some(val) => val.kind.clone(),
+                None => return self.clone(),
+            },
+            ExprKind::Group(expr) => ExprKind::Group(sub(expr)),
+            ExprKind::Variadic(name) => ExprKind::Variadic(name.clone()),
+            ExprKind::List(items) => {
+                ExprKind::List(items.iter().map(|item| item.substitute(eval_env)).collect())
+            }
+            ExprKind::Tuple(items) => {
+                ExprKind::Tuple(items.iter().map(|item| item.substitute(eval_env)).collect())
+            }
+            ExprKind::Matrix(rows) => ExprKind::Matrix(
+                rows.iter()
+                    .map(|row| row.iter().map(|entry| entry.substitute(eval_env)).collect())
+                    .collect(),
+            ),
+            ExprKind::Index { target, index } => ExprKind::Index {
+                target: sub(target),
+                index: sub(index),
+            },
+            ExprKind::Lambda { params, body } => ExprKind::Lambda {
+                params: params.clone(),
+                body: Box::new(body.substitute(&eval_env.without_vars(params))),
+            },
+            ExprKind::Let { name, value, body } => ExprKind::Let {
+                name: name.clone(),
+                value: sub(value),
+                body: Box::new(body.substitute(&eval_env.without_vars(slice::from_ref(name)))),
+            },
+            ExprKind::If {
+                cond,
+                then,
+                otherwise,
+            } => ExprKind::If {
+                cond: sub(cond),
+                then: sub(then),
+                otherwise: sub(otherwise),
+            },
+            ExprKind::Piecewise {
+                branches,
+                otherwise,
+            } => ExprKind::Piecewise {
+                branches: branches
+                    .iter()
+                    .map(|(cond, value)| (cond.substitute(eval_env), value.substitute(eval_env)))
+                    .collect(),
+                otherwise: otherwise.as_deref().map(sub),
+            },
+            ExprKind::Numeric(_) | ExprKind::Bool(_) => return self.clone(),
+        };
+        self.spanned(kind)
+    }
+    // applies op_kind element by element when either operand is a list, a scalar operand is
+    // paired with every element
+    fn broadcast(
+        &self,
+        op_kind: OperatorKind,
+        left: Expr,
+        right: Expr,
+        eval_env: &EvalEnv,
+    ) -> Option<Expr> {
+        let pairs: Vec<(Expr, Expr)> = match (&left.kind, &right.kind) {
+            (ExprKind::List(left_items), ExprKind::List(right_items)) => {
+                if left_items.len() != right_items.len() {
+                    eval_env.diag.report(&EvalError::LengthMismatch {
+                        op_kind,
+                        left: Box::new(left),
+                        right: Box::new(right),
+                    });
+                    return None;
                 }
+                zip(left_items.clone(), right_items.clone()).collect()
+            }
+            (ExprKind::List(left_items), _) => left_items
+                .iter()
+                .map(|item| (item.clone(), right.clone()))
+                .collect(),
+            (_, ExprKind::List(right_items)) => right_items
+                .iter()
+                .map(|item| (left.clone(), item.clone()))
+                .collect(),
+            _ => panic!("called broadcast without a list operand"),
+        };
+        let items = pairs
+            .into_iter()
+            .map(|(left, right)| {
+                self.spanned(ExprKind::BinOp {
+                    op_kind,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                })
+                .eval_recursive(eval_env)
+            })
+            .collect::<Option<Vec<Expr>>>()?;
+        Some(self.spanned(ExprKind::List(items)))
+    }
+    // calls a value bound to a name, a lambda is applied to args while any other value is
+    // multiplied with its single argument, as the call was a juxtaposition after all
+    fn call_value(&self, callee: &Expr, args: &[Expr], eval_env: &EvalEnv) -> Option<Expr> {
+        match (&callee.kind, args) {
+            (ExprKind::Lambda { .. }, _) => self.apply_lambda(callee, args, eval_env),
+            (_, [arg]) => self
+                .spanned(ExprKind::BinOp {
+                    op_kind: OperatorKind::Mult,
+                    left: Box::new(callee.clone()),
+                    right: Box::new(arg.clone()),
+                })
+                .eval_recursive(eval_env),
+            _ => {
+                eval_env.diag.report(&EvalError::NotAFunction {
+                    call: Box::new(self.clone()),
+                    callee: Box::new(callee.clone()),
+                });
+                None
+            }
+        }
+    }
+    // applies lambda to args for the call self
+    fn apply_lambda(&self, lambda: &Expr, args: &[Expr], eval_env: &EvalEnv) -> Option<Expr> {
+        let ExprKind::Lambda { params, body } = &lambda.kind else {
+            panic!("called apply_lambda on {}", lambda)
+        };
+        if params.len() != args.len() {
+            eval_env.diag.report(&EvalError::WrongArgCount {
+                call: Box::new(self.clone()),
+                expected: params.len(),
+                found: args.len(),
+            });
+            return None;
+        }
+        Some(self.spanned(eval_call(self, body, params, args, eval_env)?.kind))
+    }
+    // evaluates calls to functors that are always available, unless the user redefined them,
+    // self is returned as is if name with these (evaluated) args is not a builtin
+    fn eval_builtin(&self, name: &str, args: &[Expr], eval_env: &EvalEnv) -> Option<Expr> {
+        match (name, args) {
+            ("sqrt", [arg]) if arg.is_num() => Some(self.spanned(ExprKind::Numeric(
+                arg.expect_val("expected val on is_num==true").sqrt(),
+            ))),
+            ("apply", [f, args @ ..]) if f.is_lambda() => self.apply_lambda(f, args, eval_env),
+            (
+                "len",
+                [Expr {
+                    kind: ExprKind::List(items) | ExprKind::Tuple(items),
+                    ..
+                }],
+            ) => Some(self.spanned(ExprKind::Numeric(items.len() as f64))),
+            (
+                "len",
+                [Expr {
+                    kind: ExprKind::Matrix(rows),
+                    ..
+                }],
+            ) => Some(self.spanned(ExprKind::Numeric(rows.len() as f64))),
+            (
+                "sum",
+                [Expr {
+                    kind: ExprKind::List(items),
+                    ..
+                }],
+            ) => match self.added_up(items) {
+                Some(total) => total.eval_recursive(eval_env),
+                None => Some(self.spanned(ExprKind::Numeric(0.0))),
+            },
+            // mean(xs...) of the arguments, or of the items of a single list
+            ("mean", args) => {
+                let items = match args {
+                    [Expr {
+                        kind: ExprKind::List(items),
+                        ..
+                    }] => items,
+                    _ => args,
+                };
+                let Some(total) = self.added_up(items) else {
+                    eval_env.diag.report(&EvalError::NoValues {
+                        call: Box::new(self.clone()),
+                    });
+                    return None;
+                };
+                self.spanned(ExprKind::BinOp {
+                    op_kind: OperatorKind::Div,
+                    left: Box::new(total),
+                    right: Box::new(self.spanned(ExprKind::Numeric(items.len() as f64))),
+                })
+                .eval_recursive(eval_env)
+            }
+            (
+                "map",
+                [f, Expr {
+                    kind: ExprKind::List(items),
+                    ..
+                }],
+            ) if f.is_lambda() => {
+                let items = items
+                    .iter()
+                    .map(|item| self.apply_lambda(f, slice::from_ref(item), eval_env))
+                    .collect::<Option<Vec<Expr>>>()?;
+                Some(self.spanned(ExprKind::List(items)))
+            }
+            // compose(f, g) is x -> f(g(x)), built by putting the body of g in place of the
+            // parameter of f
+            (
+                "compose",
+                [Expr {
+                    kind:
+                        ExprKind::Lambda {
+                            params: f_params,
+                            body: f_body,
+                        },
+                    ..
+                }, Expr {
+                    kind:
+                        ExprKind::Lambda {
+                            params: g_params,
+                            body: g_body,
+                        },
+                    ..
+                }],
+            ) if f_params.len() == 1 => {
+                let mut g_env = EvalEnv::with_diag(eval_env.diag.clone());
+                let g_result = g_body.spanned(ExprKind::Group(g_body.clone()));
+                Rc::make_mut(&mut g_env.vars).insert(f_params[0].clone(), Box::new(g_result));
+                Some(self.spanned(ExprKind::Lambda {
+                    params: g_params.clone(),
+                    body: Box::new(f_body.substitute(&g_env)),
+                }))
             }
-            Expr::Bool(_) => self.clone(),
+            _ => self.eval_matrix_builtin(name, args, eval_env),
         }
     }
+    // items added up as a + b + c, so symbolic items are simplified like in any sum. None if
+    // there are none
+    fn added_up(&self, items: &[Expr]) -> Option<Expr> {
+        items.iter().cloned().reduce(|total, item| {
+            self.spanned(ExprKind::BinOp {
+                op_kind: OperatorKind::Plus,
+                left: Box::new(total),
+                right: Box::new(item),
+            })
+        })
+    }
     pub fn expect_val(&self, msg: &str) -> f64 {
-        match self {
-            Expr::Numeric(val) => *val,
+        match self.kind {
+            ExprKind::Numeric(val) => val,
             _ => panic!("Called expect _val on {}, with message: {}", self, msg),
         }
     }
     pub fn expect_name(&self, msg: &str) -> &String {
-        match self {
-            Expr::Variable(name) => name,
+        match &self.kind {
+            ExprKind::Variable(name) => name,
             _ => panic!("{}", msg),
         }
     }
     pub fn expect_bool(&self, msg: &str) -> bool {
-        match self {
-            Expr::Bool(val) => *val,
+        match self.kind {
+            ExprKind::Bool(val) => val,
             _ => panic!("{}", msg),
         }
     }
     pub fn is_num(&self) -> bool {
-        match self {
-            Expr::Numeric(_) => true,
-            _ => false,
-        }
+        matches!(self.kind, ExprKind::Numeric(_))
     }
     pub fn is_var(&self) -> bool {
-        match self {
-            Expr::Variable(_) => true,
-            _ => false,
-        }
+        matches!(self.kind, ExprKind::Variable(_))
     }
     pub fn is_bool(&self) -> bool {
-        match self {
-            Expr::Bool(_) => true,
-            _ => false,
+        matches!(self.kind, ExprKind::Bool(_))
+    }
+    pub fn is_lambda(&self) -> bool {
+        matches!(self.kind, ExprKind::Lambda { .. })
+    }
+    pub fn is_list(&self) -> bool {
+        matches!(self.kind, ExprKind::List(_))
+    }
+    pub fn is_collection(&self) -> bool {
+        matches!(
+            self.kind,
+            ExprKind::List(_) | ExprKind::Tuple(_) | ExprKind::Matrix(_)
+        )
+    }
+    // whether evaluation is done with the expression, as opposed to a symbolic expression
+    pub fn is_value(&self) -> bool {
+        match &self.kind {
+            ExprKind::List(items) | ExprKind::Tuple(items) => items.iter().all(Expr::is_value),
+            ExprKind::Matrix(rows) => rows.iter().flatten().all(Expr::is_value),
+            _ => self.is_num() || self.is_bool() || self.is_lambda(),
         }
     }
-    pub fn get_var_names(&self) -> Vec<String> {
-        match self {
-            Expr::BinOp {
+    // returns every Variable node in the expression, in source order
+    pub fn get_vars(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::BinOp {
                 op_kind: _,
                 left,
                 right,
-            } => [left.get_var_names(), right.get_var_names()].concat(),
-            Expr::Fun { name: _, params } => {
+            } => [left.get_vars(), right.get_vars()].concat(),
+            ExprKind::UnaryOp {
+                op_kind: _,
+                operand,
+            } => operand.get_vars(),
+            ExprKind::Fun { name: _, params } => {
+                params.iter().flat_map(|param| param.get_vars()).collect()
+            }
+            ExprKind::Numeric(_) => vec![],
+            ExprKind::Variable(_) => vec![self],
+            ExprKind::Group(expr) | ExprKind::Variadic(expr) => expr.get_vars(),
+            ExprKind::Bool(_) => vec![],
+            ExprKind::List(items) | ExprKind::Tuple(items) => {
+                items.iter().flat_map(|item| item.get_vars()).collect()
+            }
+            ExprKind::Matrix(rows) => rows.iter().flatten().flat_map(Expr::get_vars).collect(),
+            ExprKind::Index { target, index } => [target.get_vars(), index.get_vars()].concat(),
+            // only the variables that are not parameters come from outside the lambda
+            ExprKind::Lambda { params, body } => body
+                .get_vars()
+                .into_iter()
+                .filter(
+                    |var| !matches!(&var.kind, ExprKind::Variable(name) if params.contains(name)),
+                )
+                .collect(),
+            // the bound name is local to the body
+            ExprKind::Let { name, value, body } => {
+                let body_vars = body.get_vars().into_iter().filter(
+                    |var| !matches!(&var.kind, ExprKind::Variable(var_name) if var_name == name),
+                );
+                value.get_vars().into_iter().chain(body_vars).collect()
+            }
+            ExprKind::If {
+                cond,
+                then,
+                otherwise,
+            } => [cond.get_vars(), then.get_vars(), otherwise.get_vars()].concat(),
+            ExprKind::Piecewise {
+                branches,
+                otherwise,
+            } => {
                 let mut result = vec![];
+                for (cond, value) in branches {
+                    result.extend(value.get_vars());
+                    result.extend(cond.get_vars());
+                }
+                result.extend(otherwise.iter().flat_map(|otherwise| otherwise.get_vars()));
+                result
+            }
+        }
+    }
+    pub fn get_var_names(&self) -> Vec<String> {
+        self.get_vars()
+            .iter()
+            .map(|var| var.expect_name("get_vars returned a non-variable").clone())
+            .collect()
+    }
+    // returns every Fun node in the expression, in source order
+    pub fn get_funs(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::BinOp {
+                op_kind: _,
+                left,
+                right,
+            } => [left.get_funs(), right.get_funs()].concat(),
+            ExprKind::UnaryOp {
+                op_kind: _,
+                operand,
+            } => operand.get_funs(),
+            ExprKind::Fun { name: _, params } => {
+                let mut result = vec![self];
                 for param in params {
-                    if let Expr::Variable(name) = param {
-                        result.push(name.clone());
-                    }
+                    result.extend(param.get_funs());
+                }
+                result
+            }
+            ExprKind::Numeric(_) => vec![],
+            ExprKind::Variable(_) => vec![],
+            ExprKind::Group(expr) | ExprKind::Variadic(expr) => expr.get_funs(),
+            ExprKind::Bool(_) => vec![],
+            ExprKind::List(items) | ExprKind::Tuple(items) => {
+                items.iter().flat_map(|item| item.get_funs()).collect()
+            }
+            ExprKind::Matrix(rows) => rows.iter().flatten().flat_map(Expr::get_funs).collect(),
+            ExprKind::Index { target, index } => [target.get_funs(), index.get_funs()].concat(),
+            ExprKind::Lambda { params: _, body } => body.get_funs(),
+            ExprKind::Let {
+                name: _,
+                value,
+                body,
+            } => [value.get_funs(), body.get_funs()].concat(),
+            ExprKind::If {
+                cond,
+                then,
+                otherwise,
+            } => [cond.get_funs(), then.get_funs(), otherwise.get_funs()].concat(),
+            ExprKind::Piecewise {
+                branches,
+                otherwise,
+            } => {
+                let mut result = vec![];
+                for (cond, value) in branches {
+                    result.extend(value.get_funs());
+                    result.extend(cond.get_funs());
                 }
+                result.extend(otherwise.iter().flat_map(|otherwise| otherwise.get_funs()));
                 result
             }
-            Expr::Numeric(_) => vec![],
-            Expr::Variable(name) => vec![name.clone()],
-            Expr::Group(expr) => expr.get_var_names(),
-            Expr::Bool(_) => vec![],
         }
     }
-    pub fn get_fun_names(&self) -> Vec<String> {
-        match self {
-            Expr::BinOp {
+    // returns the Fun nodes that are evaluated whenever the expression is, leaving out those in a
+    // branch of a conditional or in the body of a lambda
+    pub fn get_unguarded_funs(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::BinOp {
                 op_kind: _,
                 left,
                 right,
-            } => [left.get_fun_names(), right.get_fun_names()].concat(),
-            Expr::Fun { name, params: _ } => {
-                return vec![name.clone()];
+            } => [left.get_unguarded_funs(), right.get_unguarded_funs()].concat(),
+            ExprKind::UnaryOp {
+                op_kind: _,
+                operand,
+            } => operand.get_unguarded_funs(),
+            ExprKind::Fun { name: _, params } => {
+                let mut result = vec![self];
+                for param in params {
+                    result.extend(param.get_unguarded_funs());
+                }
+                result
+            }
+            ExprKind::Numeric(_) | ExprKind::Variable(_) | ExprKind::Bool(_) => vec![],
+            ExprKind::Group(expr) | ExprKind::Variadic(expr) => expr.get_unguarded_funs(),
+            ExprKind::List(items) | ExprKind::Tuple(items) => {
+                items.iter().flat_map(Expr::get_unguarded_funs).collect()
             }
-            Expr::Numeric(_) => vec![],
-            Expr::Variable(_) => vec![],
-            Expr::Group(expr) => expr.get_fun_names(),
-            Expr::Bool(_) => vec![],
+            ExprKind::Matrix(rows) => rows
+                .iter()
+                .flatten()
+                .flat_map(Expr::get_unguarded_funs)
+                .collect(),
+            ExprKind::Index { target, index } => {
+                [target.get_unguarded_funs(), index.get_unguarded_funs()].concat()
+            }
+            ExprKind::Lambda { .. } => vec![],
+            ExprKind::Let {
+                name: _,
+                value,
+                body,
+            } => [value.get_unguarded_funs(), body.get_unguarded_funs()].concat(),
+            ExprKind::If { cond, .. } => cond.get_unguarded_funs(),
+            // only the first condition is always evaluated, or the otherwise branch if there are
+            // no conditions
+            ExprKind::Piecewise {
+                branches,
+                otherwise,
+            } => match (branches.first(), otherwise) {
+                (Some((cond, _)), _) => cond.get_unguarded_funs(),
+                (None, Some(otherwise)) => otherwise.get_unguarded_funs(),
+                (None, None) => vec![],
+            },
         }
     }
+    pub fn get_fun_names(&self) -> Vec<String> {
+        self.get_funs()
+            .iter()
+            .filter_map(|fun| match &fun.kind {
+                ExprKind::Fun { name, params: _ } => Some(name.clone()),
+                _ => None,
+            })
+            .collect()
+    }
 }
 
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Expr::BinOp {
+        match &self.kind {
+            ExprKind::BinOp {
                 op_kind,
                 left,
                 right,
             } => write!(f, "{}{}{}", left, op_kind, right),
-            Expr::Fun { name, params: args } => {
+            ExprKind::UnaryOp { op_kind, operand } => write!(f, "{}{}", op_kind, operand),
+            ExprKind::Fun { name, params: args } => {
                 let mut args_str = String::new();
                 for arg in args {
                     args_str.push_str(&arg.to_string());
@@ -362,10 +1404,220 @@ impl fmt::Display for Expr {
                 }
                 write!(f, "{}({})", name, args_str)
             }
-            Expr::Numeric(value) => write!(f, "{}", value),
-            Expr::Variable(name) => write!(f, "{}", name),
-            Expr::Group(expr) => write!(f, "({})", expr),
-            Expr::Bool(val) => write!(f, "{}", val),
+            ExprKind::Numeric(value) => write!(f, "{}", value),
+            ExprKind::Variable(name) => write!(f, "{}", name),
+            ExprKind::Group(expr) => write!(f, "({})", expr),
+            ExprKind::Variadic(name) => write!(f, "{}...", name),
+            ExprKind::Bool(val) => write!(f, "{}", val),
+            ExprKind::List(items) => write!(f, "[{}]", join_exprs(items)),
+            ExprKind::Tuple(items) => write!(f, "({})", join_exprs(items)),
+            ExprKind::Matrix(rows) => {
+                let rows: Vec<String> = rows
+                    .iter()
+                    .map(|row| format!("[{}]", join_exprs(row)))
+                    .collect();
+                write!(f, "[{}]", rows.join(","))
+            }
+            ExprKind::Index { target, index } => write!(f, "{}[{}]", target, index),
+            ExprKind::Lambda { params, body } => match &params[..] {
+                [param] => write!(f, "{}->{}", param, body),
+                _ => write!(f, "({})->{}", params.join(","), body),
+            },
+            ExprKind::Let { name, value, body } => {
+                write!(f, "let {} = {} in {}", name, value, body)
+            }
+            ExprKind::If {
+                cond,
+                then,
+                otherwise,
+            } => write!(f, "if {} then {} else {}", cond, then, otherwise),
+            ExprKind::Piecewise {
+                branches,
+                otherwise,
+            } => {
+                let mut branches_str: Vec<String> = branches
+                    .iter()
+                    .map(|(cond, value)| format!("{} if {}", value, cond))
+                    .collect();
+                branches_str.extend(otherwise.iter().map(|otherwise| otherwise.to_string()));
+                write!(f, "piecewise({})", branches_str.join(", "))
+            }
+        }
+    }
+}
+
+// a single statement of a program, statements are separated by newlines or ';'
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    // name = value
+    Assign(Expr),
+    // name(params) = body
+    FuncDef(Expr),
+    Expr(Expr),
+}
+impl Stmt {
+    pub fn from_expr(expr: Expr) -> Self {
+        match &expr.kind {
+            ExprKind::BinOp {
+                op_kind: OperatorKind::Equals,
+                left,
+                right: _,
+            } => match left.kind {
+                ExprKind::Fun { .. } => Stmt::FuncDef(expr),
+                _ => Stmt::Assign(expr),
+            },
+            _ => Stmt::Expr(expr),
         }
     }
+    pub fn expr(&self) -> &Expr {
+        match self {
+            Stmt::Assign(expr) | Stmt::FuncDef(expr) | Stmt::Expr(expr) => expr,
+        }
+    }
+    pub fn into_expr(self) -> Expr {
+        match self {
+            Stmt::Assign(expr) | Stmt::FuncDef(expr) | Stmt::Expr(expr) => expr,
+        }
+    }
+    // the variable or function a statement defines
+    pub fn declared_name(&self) -> Option<&str> {
+        let (Stmt::Assign(expr) | Stmt::FuncDef(expr)) = self else {
+            return None;
+        };
+        match &expr.kind {
+            ExprKind::BinOp { left, .. } => match &left.kind {
+                ExprKind::Variable(name) | ExprKind::Fun { name, params: _ } => Some(name),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    pub fn eval(&self, eval_env: &mut EvalEnv) -> Option<Expr> {
+        self.expr().eval(eval_env)
+    }
+}
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.expr().fmt(f)
+    }
+}
+
+// the statements of an input in source order,
+// statements that failed to parse are left out and their errors are kept instead
+#[derive(Debug, Default)]
+pub struct Program {
+    pub stmts: Vec<Stmt>,
+    pub errors: Vec<ParserError>,
+}
+impl Program {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+    // evaluates the statements in order, a statement failing to evaluate does not stop the rest
+    pub fn eval(&self, eval_env: &mut EvalEnv) -> Vec<Option<Expr>> {
+        self.stmts.iter().map(|stmt| stmt.eval(eval_env)).collect()
+    }
+}
+
+// reports an error if operand has a known type that op_kind does not accept
+fn check_operand_type(
+    op_kind: OperatorKind,
+    expected: OperandType,
+    operand: &Expr,
+    eval_env: &EvalEnv,
+) -> Option<()> {
+    let found = match operand.kind {
+        ExprKind::Numeric(_) => Some(OperandType::Numeric),
+        ExprKind::Bool(_) => Some(OperandType::Bool),
+        // functions can only be called and tuples only indexed, both can be compared
+        ExprKind::Lambda { .. } | ExprKind::Tuple(_) => None,
+        _ => return Some(()),
+    };
+    if found != Some(expected) {
+        eval_env.diag.report(&EvalError::InvalidOperand {
+            op_kind,
+            expected,
+            operand: Box::new(operand.clone()),
+        });
+        return None;
+    }
+    Some(())
+}
+// the lambda a function defined by clauses stands for. a single clause f(params) = body taking
+// plain names is params -> body, otherwise the lambda calls f by name so the clauses are still
+// matched. a function overloaded for several numbers of arguments has no single lambda
+fn as_lambda(arities: &BTreeMap<usize, Vec<Expr>>) -> Option<ExprKind> {
+    if arities.len() != 1 {
+        return None;
+    }
+    let clauses = arities.values().next()?;
+    let (name, patterns, body) = clauses.first()?.clause_parts();
+    let plain_names = patterns.iter().all(Expr::is_var);
+    if clauses.len() == 1 && clauses[0].is_catch_all() && plain_names {
+        let params = patterns.iter().map(Expr::to_string).collect();
+        return Some(ExprKind::Lambda {
+            params,
+            body: Box::new(body.clone()),
+        });
+    }
+    let params: Vec<String> = (1..=patterns.len()).map(|i| format!("x{}", i)).collect();
+    let args = params
+        .iter()
+        .map(|param| Expr::from(ExprKind::Variable(param.clone())))
+        .collect();
+    Some(ExprKind::Lambda {
+        params,
+        body: Box::new(Expr::from(ExprKind::Fun {
+            name: name.to_string(),
+            params: args,
+        })),
+    })
+}
+// value of an evaluated condition, None if it is still symbolic
+fn eval_condition(cond: &Expr, eval_env: &EvalEnv) -> Option<Option<bool>> {
+    match cond.kind {
+        ExprKind::Bool(val) => Some(Some(val)),
+        ExprKind::Numeric(_) => {
+            eval_env.diag.report(&EvalError::InvalidCondition {
+                cond: Box::new(cond.clone()),
+            });
+            None
+        }
+        _ => Some(None),
+    }
+}
+// evaluates a function body with params bound to args. other variables of the body are those of
+// the environment the function is defined in, not those of the caller. functions are available
+// right away, so recursive calls are evaluated one level deeper
+fn eval_call(
+    call: &Expr,
+    body: &Expr,
+    params: &[String],
+    args: &[Expr],
+    eval_env: &EvalEnv,
+) -> Option<Expr> {
+    if eval_env.depth >= eval_env.max_depth {
+        eval_env.diag.report(&EvalError::RecursionLimit {
+            call: Box::new(call.clone()),
+            limit: eval_env.max_depth,
+        });
+        return None;
+    }
+    let call_env = EvalEnv {
+        vars: Rc::new(
+            zip(params, args)
+                .map(|(param, arg)| (param.clone(), Box::new(arg.clone())))
+                .collect(),
+        ),
+        globals: Some(eval_env.globals().clone()),
+        funcs: eval_env.funcs.clone(),
+        diag: eval_env.diag.clone(),
+        max_depth: eval_env.max_depth,
+        depth: eval_env.depth + 1,
+    };
+    body.eval_recursive(&call_env)
+}
+fn join_exprs(exprs: &[Expr]) -> String {
+    let exprs: Vec<String> = exprs.iter().map(Expr::to_string).collect();
+    exprs.join(",")
 }
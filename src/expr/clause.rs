@@ -0,0 +1,361 @@
+use std::iter::zip;
+
+use super::{eval_call, EvalEnv, Expr, ExprKind, OperatorKind};
+
+// outcome of matching arguments against the parameter patterns of a clause
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Match {
+    Matches,
+    Fails,
+    // an argument is symbolic, so whether the clause applies depends on its value
+    Unknown,
+}
+
+impl Expr {
+    // whether expr can be a parameter of a clause: a name, a number or boolean, or a tuple, list or
+    // constructor term such as pair(a, b) made of patterns
+    pub fn is_pattern(&self) -> bool {
+        match &self.kind {
+            ExprKind::Variable(_) | ExprKind::Numeric(_) | ExprKind::Bool(_) => true,
+            ExprKind::UnaryOp {
+                op_kind: OperatorKind::Min,
+                operand,
+            } => operand.is_num(),
+            ExprKind::Fun { params: items, .. }
+            | ExprKind::List(items)
+            | ExprKind::Tuple(items) => items.iter().all(Expr::is_pattern),
+            _ => false,
+        }
+    }
+    // whether expr can be a parameter of a definition: a pattern, a name with a default value as
+    // in digits = 0, or a name taking the remaining arguments as in xs...
+    pub fn is_param(&self) -> bool {
+        self.is_pattern() || self.named_arg().is_some() || self.is_variadic()
+    }
+    pub fn is_variadic(&self) -> bool {
+        matches!(&self.kind, ExprKind::Variadic(name) if name.is_var())
+    }
+    // the name and value of an argument passed by name as in digits = 2, which is also how a
+    // parameter with a default value is written
+    pub fn named_arg(&self) -> Option<(&str, &Expr)> {
+        let ExprKind::BinOp {
+            op_kind: OperatorKind::Equals,
+            left,
+            right,
+        } = &self.kind
+        else {
+            return None;
+        };
+        match &left.kind {
+            ExprKind::Variable(name) => Some((name, right)),
+            _ => None,
+        }
+    }
+    // the name a parameter binds the whole argument to, None for a pattern taking it apart
+    pub fn param_name(&self) -> Option<&str> {
+        match &self.kind {
+            ExprKind::Variable(name) => Some(name),
+            ExprKind::Variadic(name) => name.param_name(),
+            _ => self.named_arg().map(|(name, _)| name),
+        }
+    }
+    // the names a parameter binds, from left to right and repeated names included
+    pub fn bound_names(&self) -> Vec<&str> {
+        match &self.kind {
+            ExprKind::Fun { params: items, .. }
+            | ExprKind::List(items)
+            | ExprKind::Tuple(items) => items.iter().flat_map(Expr::bound_names).collect(),
+            _ => self.param_name().into_iter().collect(),
+        }
+    }
+    // the fewest and most arguments a clause takes, there is no most for a variadic one
+    pub fn arity_range(&self) -> (usize, Option<usize>) {
+        let (_, params, _) = self.clause_parts();
+        let required = params
+            .iter()
+            .filter(|param| param.named_arg().is_none() && !param.is_variadic())
+            .count();
+        match params.last().is_some_and(Expr::is_variadic) {
+            true => (required, None),
+            false => (required, Some(params.len())),
+        }
+    }
+    // the clause with the variables its default values refer to captured, as they are when it is
+    // defined. the parameters before a default are left for when it is called
+    pub fn capture_defaults(&self, eval_env: &EvalEnv) -> Expr {
+        let ExprKind::BinOp {
+            op_kind,
+            left,
+            right,
+        } = &self.kind
+        else {
+            panic!("{} is not a clause", self)
+        };
+        let (name, params, _) = self.clause_parts();
+        let names: Vec<String> = params
+            .iter()
+            .filter_map(|param| Some(param.param_name()?.to_string()))
+            .collect();
+        let captured = eval_env.without_vars(&names);
+        let params = params
+            .iter()
+            .map(|param| match &param.kind {
+                ExprKind::BinOp {
+                    op_kind: OperatorKind::Equals,
+                    left: param_name,
+                    right: default,
+                } => param.spanned(ExprKind::BinOp {
+                    op_kind: OperatorKind::Equals,
+                    left: param_name.clone(),
+                    right: Box::new(default.substitute(&captured)),
+                }),
+                _ => param.clone(),
+            })
+            .collect();
+        let left = left.spanned(ExprKind::Fun {
+            name: name.to_string(),
+            params,
+        });
+        self.spanned(ExprKind::BinOp {
+            op_kind: *op_kind,
+            left: Box::new(left),
+            right: right.clone(),
+        })
+    }
+    // the arguments bound to params by bind_args, with the parameters that were not passed taking
+    // their defaults. a default is evaluated for the call self, and can refer to the parameters
+    // before it
+    pub fn fill_defaults(
+        &self,
+        params: &[Expr],
+        slots: Vec<Option<Expr>>,
+        eval_env: &EvalEnv,
+    ) -> Option<Vec<Expr>> {
+        let mut names = vec![];
+        let mut values = vec![];
+        let mut args = vec![];
+        for (param, slot) in zip(params, slots) {
+            let arg = match (slot, param.named_arg()) {
+                (Some(arg), _) => arg,
+                (None, Some((_, default))) => eval_call(self, default, &names, &values, eval_env)?,
+                (None, None) => unreachable!("bind_args only leaves out parameters with defaults"),
+            };
+            if let Some(name) = param.param_name() {
+                names.push(name.to_string());
+                values.push(arg.clone());
+            }
+            args.push(arg);
+        }
+        Some(args)
+    }
+    // the name(patterns) side and the body of a clause name(patterns) = body
+    pub fn clause_parts(&self) -> (&str, &[Expr], &Expr) {
+        let ExprKind::BinOp { left, right, .. } = &self.kind else {
+            panic!("{} is not a clause", self)
+        };
+        let ExprKind::Fun { name, params } = &left.kind else {
+            panic!("{} is not a clause", self)
+        };
+        (name, params, right)
+    }
+    // whether the clause has only distinct names as parameters, so it applies to any call it
+    // can be passed
+    pub fn is_catch_all(&self) -> bool {
+        let (_, patterns, _) = self.clause_parts();
+        patterns.iter().enumerate().all(|(i, pattern)| {
+            pattern.param_name().is_some_and(|name| {
+                !patterns[..i]
+                    .iter()
+                    .any(|earlier| earlier.param_name() == Some(name))
+            })
+        })
+    }
+    // whether two clauses match the same calls, so the later one replaces the earlier
+    pub fn same_patterns(&self, other: &Expr) -> bool {
+        let (_, patterns, _) = self.clause_parts();
+        let (_, other_patterns, _) = other.clause_parts();
+        if !all_same_shape(patterns, other_patterns) {
+            return false;
+        }
+        // f(x, x) only matches equal arguments while f(x, y) matches any
+        let names: Vec<&str> = patterns.iter().flat_map(Expr::bound_names).collect();
+        let other_names: Vec<&str> = other_patterns.iter().flat_map(Expr::bound_names).collect();
+        names.iter().zip(&other_names).all(|(name, other_name)| {
+            names
+                .iter()
+                .zip(&other_names)
+                .all(|(second, other_second)| (name == second) == (other_name == other_second))
+        })
+    }
+}
+
+// lines up the arguments of a call with the parameters of a clause. arguments passed by name go
+// to the parameter of that name and the others fill the remaining parameters in order, a variadic
+// parameter gets a list of the arguments left over unless it is passed a list by name, and a
+// parameter that is not passed is left for its default. None if the call doesn't fit the
+// parameters
+pub fn bind_args(params: &[Expr], args: &[Expr]) -> Option<Vec<Option<Expr>>> {
+    let variadic = params.last().filter(|param| param.is_variadic());
+    let fixed = &params[..params.len() - variadic.is_some() as usize];
+    let mut slots: Vec<Option<Expr>> = vec![None; fixed.len()];
+    let mut named_rest = None;
+    let mut positional = vec![];
+    for arg in args {
+        match arg.named_arg() {
+            Some((name, value)) if variadic.and_then(Expr::param_name) == Some(name) => {
+                if named_rest.replace(value.clone()).is_some() {
+                    return None;
+                }
+            }
+            Some((name, value)) => {
+                let i = fixed
+                    .iter()
+                    .position(|param| param.param_name() == Some(name))?;
+                // passed twice
+                if slots[i].replace(value.clone()).is_some() {
+                    return None;
+                }
+            }
+            None => positional.push(arg.clone()),
+        }
+    }
+    let mut positional = positional.into_iter();
+    for slot in slots.iter_mut().filter(|slot| slot.is_none()) {
+        *slot = positional.next();
+    }
+    let rest: Vec<Expr> = positional.collect();
+    if zip(&slots, fixed).any(|(slot, param)| slot.is_none() && param.named_arg().is_none()) {
+        return None;
+    }
+    let mut bound = slots;
+    match (variadic, named_rest) {
+        (Some(_), Some(list)) if rest.is_empty() => bound.push(Some(list)),
+        (Some(_), None) => bound.push(Some(Expr::from(ExprKind::List(rest)))),
+        (None, _) if rest.is_empty() => (),
+        _ => return None,
+    }
+    Some(bound)
+}
+// the first parameter without a default that a call passing args leaves without a value, as the
+// arguments passed by name don't include it and too few others are passed
+pub fn missing_param<'a>(params: &'a [Expr], args: &[Expr]) -> Option<&'a Expr> {
+    let named: Vec<&str> = args
+        .iter()
+        .filter_map(|arg| Some(arg.named_arg()?.0))
+        .collect();
+    params
+        .iter()
+        .filter(|param| !param.is_variadic())
+        .filter(|param| param.param_name().is_none_or(|name| !named.contains(&name)))
+        .skip(args.len() - named.len())
+        .find(|param| param.named_arg().is_none())
+}
+// matches args against the patterns of a clause, binding the names in the patterns to the parts
+// of the arguments they stand for
+pub fn match_all(patterns: &[Expr], args: &[Expr], bound: &mut Vec<(String, Expr)>) -> Match {
+    if patterns.len() != args.len() {
+        return Match::Fails;
+    }
+    let mut result = Match::Matches;
+    for (pattern, arg) in patterns.iter().zip(args) {
+        match match_pattern(pattern, arg, bound) {
+            Match::Fails => return Match::Fails,
+            Match::Unknown => result = Match::Unknown,
+            Match::Matches => (),
+        }
+    }
+    result
+}
+fn match_pattern(pattern: &Expr, arg: &Expr, bound: &mut Vec<(String, Expr)>) -> Match {
+    // values and constructor terms have a known shape, anything else may still become anything
+    let known = arg.is_value() || arg.is_collection() || arg.is_lambda();
+    if let Some((name, _)) = pattern.named_arg() {
+        // the default only matters for lining up the arguments
+        return match_pattern(
+            &Expr::from(ExprKind::Variable(name.to_string())),
+            arg,
+            bound,
+        );
+    }
+    match (&pattern.kind, &arg.kind) {
+        (ExprKind::Variadic(name), _) => match_pattern(name, arg, bound),
+        (ExprKind::Variable(name), _) => {
+            match bound.iter().find(|(bound_name, _)| bound_name == name) {
+                // a name used twice has to match equal arguments
+                Some((_, val)) if val == arg => Match::Matches,
+                Some((_, val)) if val.is_value() && arg.is_value() => Match::Fails,
+                Some(_) => Match::Unknown,
+                None => {
+                    bound.push((name.clone(), arg.clone()));
+                    Match::Matches
+                }
+            }
+        }
+        (
+            ExprKind::Fun { name, params },
+            ExprKind::Fun {
+                name: arg_name,
+                params: arg_params,
+            },
+        ) => match name == arg_name {
+            true => match_all(params, arg_params, bound),
+            false => Match::Fails,
+        },
+        (ExprKind::Tuple(patterns), ExprKind::Tuple(items))
+        | (ExprKind::List(patterns), ExprKind::List(items)) => match_all(patterns, items, bound),
+        (ExprKind::Fun { .. } | ExprKind::Tuple(_) | ExprKind::List(_), _) => match known {
+            true => Match::Fails,
+            false => Match::Unknown,
+        },
+        // number and boolean literals
+        _ => match (known, literal_value(pattern)) {
+            (true, Some(literal)) if literal == arg.kind => Match::Matches,
+            (true, _) => Match::Fails,
+            (false, _) => Match::Unknown,
+        },
+    }
+}
+fn literal_value(pattern: &Expr) -> Option<ExprKind> {
+    match &pattern.kind {
+        ExprKind::Numeric(_) | ExprKind::Bool(_) => Some(pattern.kind.clone()),
+        ExprKind::UnaryOp {
+            op_kind: OperatorKind::Min,
+            operand,
+        } => match operand.kind {
+            ExprKind::Numeric(val) => Some(ExprKind::Numeric(-val)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+// patterns with the same shape, where names match any other name
+fn same_shape(pattern: &Expr, other: &Expr) -> bool {
+    match (&pattern.kind, &other.kind) {
+        (ExprKind::Variable(_), ExprKind::Variable(_))
+        | (ExprKind::Variadic(_), ExprKind::Variadic(_)) => true,
+        (ExprKind::BinOp { .. }, ExprKind::BinOp { .. })
+            if pattern.named_arg().is_some() && other.named_arg().is_some() =>
+        {
+            true
+        }
+        (
+            ExprKind::Fun { name, params },
+            ExprKind::Fun {
+                name: other_name,
+                params: other_params,
+            },
+        ) => name == other_name && all_same_shape(params, other_params),
+        (ExprKind::Tuple(items), ExprKind::Tuple(other_items))
+        | (ExprKind::List(items), ExprKind::List(other_items)) => {
+            all_same_shape(items, other_items)
+        }
+        _ => literal_value(pattern).is_some() && literal_value(pattern) == literal_value(other),
+    }
+}
+fn all_same_shape(patterns: &[Expr], others: &[Expr]) -> bool {
+    patterns.len() == others.len()
+        && patterns
+            .iter()
+            .zip(others)
+            .all(|(pattern, other)| same_shape(pattern, other))
+}
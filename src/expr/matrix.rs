@@ -0,0 +1,604 @@
+use std::{iter::zip, slice};
+
+use super::{EvalEnv, Expr, ExprKind, OperandType, OperatorKind};
+use crate::diag::EvalError;
+
+type Rows = Vec<Vec<Expr>>;
+
+// matrices up to this size are computed by cofactor expansion, which is exact for integer entries
+// and also works for symbolic ones. larger numeric matrices use gaussian elimination
+const COFACTOR_MAX_DIM: usize = 3;
+// during elimination, pivots this small relative to the largest entry count as 0
+const PIVOT_TOLERANCE: f64 = 1e-12;
+
+impl Expr {
+    pub fn is_matrix(&self) -> bool {
+        matches!(self.kind, ExprKind::Matrix(_))
+    }
+    // evaluates left op_kind right where at least one operand is a matrix, lists stand for
+    // column vectors on the right and row vectors on the left of a product
+    pub(super) fn eval_matrix_binop(
+        &self,
+        op_kind: OperatorKind,
+        left: Expr,
+        right: Expr,
+        eval_env: &EvalEnv,
+    ) -> Option<Expr> {
+        let mismatch = |left: &Expr, right: &Expr| {
+            eval_env.diag.report(&EvalError::DimensionMismatch {
+                call: Box::new(self.clone()),
+                left: Box::new(left.clone()),
+                right: Box::new(right.clone()),
+            });
+            None
+        };
+        let rows = match (op_kind, &left.kind, &right.kind) {
+            (OperatorKind::Plus | OperatorKind::Min, ExprKind::Matrix(a), ExprKind::Matrix(b)) => {
+                if dims(a) != dims(b) {
+                    return mismatch(&left, &right);
+                }
+                zip(a, b)
+                    .map(|(a_row, b_row)| {
+                        zip(a_row, b_row)
+                            .map(|(x, y)| self.combine(op_kind, x, y, eval_env))
+                            .collect()
+                    })
+                    .collect::<Option<Rows>>()?
+            }
+            (OperatorKind::Mult, ExprKind::Matrix(a), ExprKind::Matrix(b)) => {
+                if a[0].len() != b.len() {
+                    return mismatch(&left, &right);
+                }
+                self.mat_mul(a, b, eval_env)?
+            }
+            (OperatorKind::Mult, ExprKind::Matrix(a), ExprKind::List(v)) => {
+                if a[0].len() != v.len() {
+                    return mismatch(&left, &right);
+                }
+                let column = v.iter().map(|x| vec![x.clone()]).collect::<Rows>();
+                let product = self.mat_mul(a, &column, eval_env)?;
+                let items = product.into_iter().flatten().collect();
+                return Some(self.spanned(ExprKind::List(items)));
+            }
+            (OperatorKind::Mult, ExprKind::List(v), ExprKind::Matrix(b)) => {
+                if v.len() != b.len() {
+                    return mismatch(&left, &right);
+                }
+                let product = self.mat_mul(slice::from_ref(v), b, eval_env)?;
+                let items = product.into_iter().flatten().collect();
+                return Some(self.spanned(ExprKind::List(items)));
+            }
+            // a number or a symbol applies to each entry, like it does to the items of a list
+            (
+                OperatorKind::Plus | OperatorKind::Min | OperatorKind::Mult | OperatorKind::Div,
+                ExprKind::Matrix(a),
+                _,
+            ) if !right.is_collection() => {
+                self.map_entries(a, |x| self.combine(op_kind, x, &right, eval_env))?
+            }
+            (
+                OperatorKind::Plus | OperatorKind::Min | OperatorKind::Mult | OperatorKind::Div,
+                _,
+                ExprKind::Matrix(b),
+            ) if !left.is_collection() => {
+                self.map_entries(b, |x| self.combine(op_kind, &left, x, eval_env))?
+            }
+            (OperatorKind::Pow, ExprKind::Matrix(a), _) if !right.is_collection() => {
+                let ExprKind::Numeric(power) = right.kind else {
+                    // the power is still symbolic
+                    return Some(self.spanned(ExprKind::BinOp {
+                        op_kind,
+                        left: Box::new(left),
+                        right: Box::new(right),
+                    }));
+                };
+                self.matrix_power(&left, a, power, eval_env)?
+            }
+            (OperatorKind::DoubleEquals | OperatorKind::NotEquals, _, _) => {
+                if !left.is_value() || !right.is_value() {
+                    return Some(self.spanned(ExprKind::BinOp {
+                        op_kind,
+                        left: Box::new(left),
+                        right: Box::new(right),
+                    }));
+                }
+                let equal = left == right;
+                return Some(self.spanned(ExprKind::Bool(
+                    equal == (op_kind == OperatorKind::DoubleEquals),
+                )));
+            }
+            (OperatorKind::Equals, _, _) => {
+                return Some(self.spanned(ExprKind::BinOp {
+                    op_kind,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                }))
+            }
+            (
+                OperatorKind::Plus | OperatorKind::Min | OperatorKind::Mult | OperatorKind::Div,
+                _,
+                _,
+            )
+            | (OperatorKind::Pow, ExprKind::Matrix(_), _) => return mismatch(&left, &right),
+            // comparisons and logic don't apply to matrices
+            _ => {
+                let matrix = if left.is_matrix() { left } else { right };
+                eval_env.diag.report(&EvalError::InvalidOperand {
+                    op_kind,
+                    expected: op_kind.operand_type().unwrap_or(OperandType::Numeric),
+                    operand: Box::new(matrix),
+                });
+                return None;
+            }
+        };
+        Some(self.spanned(ExprKind::Matrix(rows)))
+    }
+    // builtins taking or producing matrices, self is returned unchanged when none applies
+    pub(super) fn eval_matrix_builtin(
+        &self,
+        name: &str,
+        args: &[Expr],
+        eval_env: &EvalEnv,
+    ) -> Option<Expr> {
+        let matrix = |rows| Some(self.spanned(ExprKind::Matrix(rows)));
+        match (name, args) {
+            (
+                "transpose",
+                [Expr {
+                    kind: ExprKind::Matrix(rows),
+                    ..
+                }],
+            ) => matrix(transpose(rows)),
+            (
+                "det",
+                [m @ Expr {
+                    kind: ExprKind::Matrix(rows),
+                    ..
+                }],
+            ) => {
+                self.expect_square(m, rows, eval_env)?;
+                self.determinant(rows, eval_env)
+            }
+            (
+                "inverse",
+                [m @ Expr {
+                    kind: ExprKind::Matrix(rows),
+                    ..
+                }],
+            ) => matrix(self.inverse(m, rows, eval_env)?),
+            (
+                "rank",
+                [Expr {
+                    kind: ExprKind::Matrix(rows),
+                    ..
+                }],
+            ) => {
+                // the rank of a symbolic matrix depends on the values of its entries
+                let Some(mut values) = numeric_rows(rows) else {
+                    return Some(self.clone());
+                };
+                let cols = values[0].len();
+                let (rank, _) = gauss_jordan(&mut values, cols);
+                Some(self.spanned(ExprKind::Numeric(rank as f64)))
+            }
+            (
+                "solve",
+                [a @ Expr {
+                    kind: ExprKind::Matrix(rows),
+                    ..
+                }, b],
+            ) if b.is_list() || b.is_matrix() => self.solve(a, rows, b, eval_env),
+            ("identity", [n]) if n.is_num() => {
+                let n = self.dimension(n, eval_env)?;
+                matrix(identity(n))
+            }
+            ("zeros", [n]) if n.is_num() => {
+                let n = self.dimension(n, eval_env)?;
+                matrix(vec![vec![Expr::from(ExprKind::Numeric(0.0)); n]; n])
+            }
+            ("zeros", [m, n]) if m.is_num() && n.is_num() => {
+                let m = self.dimension(m, eval_env)?;
+                let n = self.dimension(n, eval_env)?;
+                matrix(vec![vec![Expr::from(ExprKind::Numeric(0.0)); n]; m])
+            }
+            // not a builtin, the call stays symbolic with its arguments evaluated
+            _ => Some(self.spanned(ExprKind::Fun {
+                name: name.to_string(),
+                params: args.to_vec(),
+            })),
+        }
+    }
+    // left op_kind right for entries of a matrix, terms that are 0 and factors that are 1 are left
+    // out so products of symbolic matrices stay readable
+    fn combine(
+        &self,
+        op_kind: OperatorKind,
+        left: &Expr,
+        right: &Expr,
+        eval_env: &EvalEnv,
+    ) -> Option<Expr> {
+        let is = |expr: &Expr, val: f64| expr.kind == ExprKind::Numeric(val);
+        match op_kind {
+            OperatorKind::Plus if is(left, 0.0) => return Some(right.clone()),
+            OperatorKind::Plus | OperatorKind::Min if is(right, 0.0) => return Some(left.clone()),
+            OperatorKind::Mult if is(left, 0.0) || is(right, 0.0) => {
+                return Some(self.spanned(ExprKind::Numeric(0.0)))
+            }
+            OperatorKind::Mult if is(left, 1.0) => return Some(right.clone()),
+            OperatorKind::Mult | OperatorKind::Div if is(right, 1.0) => return Some(left.clone()),
+            OperatorKind::Min if is(left, 0.0) => {
+                return self
+                    .spanned(ExprKind::UnaryOp {
+                        op_kind,
+                        operand: Box::new(group_operand(OperatorKind::Mult, right, true)),
+                    })
+                    .eval_recursive(eval_env)
+            }
+            _ => (),
+        }
+        self.spanned(ExprKind::BinOp {
+            op_kind,
+            left: Box::new(group_operand(op_kind, left, false)),
+            right: Box::new(group_operand(op_kind, right, true)),
+        })
+        .eval_recursive(eval_env)
+    }
+    fn map_entries(&self, rows: &[Vec<Expr>], f: impl Fn(&Expr) -> Option<Expr>) -> Option<Rows> {
+        rows.iter()
+            .map(|row| row.iter().map(&f).collect())
+            .collect()
+    }
+    // product of an m×n and an n×p matrix
+    fn mat_mul(&self, a: &[Vec<Expr>], b: &[Vec<Expr>], eval_env: &EvalEnv) -> Option<Rows> {
+        let b_cols = transpose(b);
+        a.iter()
+            .map(|row| {
+                b_cols
+                    .iter()
+                    .map(|col| {
+                        let mut sum = Expr::from(ExprKind::Numeric(0.0));
+                        for (x, y) in zip(row, col) {
+                            let product = self.combine(OperatorKind::Mult, x, y, eval_env)?;
+                            sum = self.combine(OperatorKind::Plus, &sum, &product, eval_env)?;
+                        }
+                        Some(sum)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+    fn matrix_power(
+        &self,
+        matrix: &Expr,
+        rows: &[Vec<Expr>],
+        power: f64,
+        eval_env: &EvalEnv,
+    ) -> Option<Rows> {
+        self.expect_square(matrix, rows, eval_env)?;
+        if power.fract() != 0.0 || !power.is_finite() {
+            eval_env.diag.report(&EvalError::NonIntegerPower {
+                call: Box::new(self.clone()),
+            });
+            return None;
+        }
+        // a negative power is a positive power of the inverse
+        let mut base = match power < 0.0 {
+            true => self.inverse(matrix, rows, eval_env)?,
+            false => rows.to_vec(),
+        };
+        let mut power = power.abs() as u64;
+        let mut result = identity(rows.len());
+        // square and multiply, so large powers take few products
+        while power > 0 {
+            if power % 2 == 1 {
+                result = self.mat_mul(&result, &base, eval_env)?;
+            }
+            power /= 2;
+            if power > 0 {
+                base = self.mat_mul(&base, &base, eval_env)?;
+            }
+        }
+        Some(result)
+    }
+    fn determinant(&self, rows: &[Vec<Expr>], eval_env: &EvalEnv) -> Option<Expr> {
+        if let Some(mut values) = numeric_rows(rows).filter(|_| rows.len() > COFACTOR_MAX_DIM) {
+            let (_, det) = gauss_jordan(&mut values, rows.len());
+            return Some(self.spanned(ExprKind::Numeric(det)));
+        }
+        if rows.len() == 1 {
+            return Some(rows[0][0].clone());
+        }
+        // expansion along the first row
+        let mut det = Expr::from(ExprKind::Numeric(0.0));
+        for (col, entry) in rows[0].iter().enumerate() {
+            if entry.kind == ExprKind::Numeric(0.0) {
+                continue;
+            }
+            let minor = self.determinant(&minor(rows, 0, col), eval_env)?;
+            let term = self.combine(OperatorKind::Mult, entry, &minor, eval_env)?;
+            let op_kind = match col % 2 {
+                0 => OperatorKind::Plus,
+                _ => OperatorKind::Min,
+            };
+            det = self.combine(op_kind, &det, &term, eval_env)?;
+        }
+        Some(det)
+    }
+    fn inverse(&self, matrix: &Expr, rows: &[Vec<Expr>], eval_env: &EvalEnv) -> Option<Rows> {
+        self.expect_square(matrix, rows, eval_env)?;
+        let n = rows.len();
+        if let Some(values) = numeric_rows(rows).filter(|_| n > COFACTOR_MAX_DIM) {
+            let augmented = zip(values, identity_values(n))
+                .map(|(row, id_row)| [row, id_row].concat())
+                .collect();
+            return self.eliminate_augmented(matrix, augmented, n, eval_env);
+        }
+        let det = self.determinant(rows, eval_env)?;
+        if det.kind == ExprKind::Numeric(0.0) {
+            return self.report_singular(matrix, eval_env);
+        }
+        if n == 1 {
+            let one = Expr::from(ExprKind::Numeric(1.0));
+            return Some(vec![vec![self.combine(
+                OperatorKind::Div,
+                &one,
+                &det,
+                eval_env,
+            )?]]);
+        }
+        // the adjugate divided by the determinant
+        (0..n)
+            .map(|row| {
+                (0..n)
+                    .map(|col| {
+                        let cofactor = self.determinant(&minor(rows, col, row), eval_env)?;
+                        let cofactor = match (row + col) % 2 {
+                            0 => cofactor,
+                            _ => self
+                                .spanned(ExprKind::UnaryOp {
+                                    op_kind: OperatorKind::Min,
+                                    operand: Box::new(group_operand(
+                                        OperatorKind::Mult,
+                                        &cofactor,
+                                        true,
+                                    )),
+                                })
+                                .eval_recursive(eval_env)?,
+                        };
+                        self.combine(OperatorKind::Div, &cofactor, &det, eval_env)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+    // x such that a x = b, b is a list for a single right hand side or a matrix with one column
+    // per right hand side
+    fn solve(&self, a: &Expr, rows: &[Vec<Expr>], b: &Expr, eval_env: &EvalEnv) -> Option<Expr> {
+        self.expect_square(a, rows, eval_env)?;
+        let b_rows = match &b.kind {
+            ExprKind::List(items) => items.iter().map(|item| vec![item.clone()]).collect(),
+            ExprKind::Matrix(b_rows) => b_rows.clone(),
+            _ => unreachable!("solve is only called with a list or matrix right hand side"),
+        };
+        if b_rows.len() != rows.len() {
+            eval_env.diag.report(&EvalError::DimensionMismatch {
+                call: Box::new(self.clone()),
+                left: Box::new(a.clone()),
+                right: Box::new(b.clone()),
+            });
+            return None;
+        }
+        let n = rows.len();
+        let numeric = numeric_rows(rows)
+            .zip(numeric_rows(&b_rows))
+            .filter(|_| n > COFACTOR_MAX_DIM);
+        let solution = match numeric {
+            Some((values, b_values)) => {
+                let augmented = zip(values, b_values)
+                    .map(|(row, b_row)| [row, b_row].concat())
+                    .collect();
+                self.eliminate_augmented(a, augmented, n, eval_env)?
+            }
+            None => {
+                let inverse = self.inverse(a, rows, eval_env)?;
+                self.mat_mul(&inverse, &b_rows, eval_env)?
+            }
+        };
+        Some(match b.kind {
+            ExprKind::List(_) => {
+                self.spanned(ExprKind::List(solution.into_iter().flatten().collect()))
+            }
+            _ => self.spanned(ExprKind::Matrix(solution)),
+        })
+    }
+    // reduces [a | b] so a becomes the identity, what is left of b is a^-1 b
+    fn eliminate_augmented(
+        &self,
+        matrix: &Expr,
+        mut augmented: Vec<Vec<f64>>,
+        n: usize,
+        eval_env: &EvalEnv,
+    ) -> Option<Rows> {
+        let (rank, _) = gauss_jordan(&mut augmented, n);
+        if rank < n {
+            return self.report_singular(matrix, eval_env);
+        }
+        Some(
+            augmented
+                .into_iter()
+                .map(|row| {
+                    row[n..]
+                        .iter()
+                        // adding 0 turns -0 into 0
+                        .map(|val| self.spanned(ExprKind::Numeric(val + 0.0)))
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+    fn expect_square(&self, matrix: &Expr, rows: &[Vec<Expr>], eval_env: &EvalEnv) -> Option<()> {
+        if rows.len() != rows[0].len() {
+            eval_env.diag.report(&EvalError::NotSquare {
+                call: Box::new(self.clone()),
+                matrix: Box::new(matrix.clone()),
+            });
+            return None;
+        }
+        Some(())
+    }
+    fn report_singular(&self, matrix: &Expr, eval_env: &EvalEnv) -> Option<Rows> {
+        eval_env.diag.report(&EvalError::SingularMatrix {
+            call: Box::new(self.clone()),
+            matrix: Box::new(matrix.clone()),
+        });
+        None
+    }
+    // size argument of a matrix constructor, which has to be a positive integer
+    fn dimension(&self, size: &Expr, eval_env: &EvalEnv) -> Option<usize> {
+        let size = size.expect_val("expected val on is_num==true");
+        if size.fract() != 0.0 || size < 1.0 || !size.is_finite() {
+            eval_env.diag.report(&EvalError::InvalidDimension {
+                call: Box::new(self.clone()),
+            });
+            return None;
+        }
+        Some(size as usize)
+    }
+}
+
+// a list of equally long, non-empty lists is a matrix with those lists as rows
+pub(super) fn list_or_matrix(items: Vec<Expr>) -> ExprKind {
+    let row_len = match items.first().map(|item| &item.kind) {
+        Some(ExprKind::List(row)) if !row.is_empty() => row.len(),
+        _ => return ExprKind::List(items),
+    };
+    let is_row = |item: &Expr| matches!(&item.kind, ExprKind::List(row) if row.len() == row_len);
+    if !items.iter().all(is_row) {
+        return ExprKind::List(items);
+    }
+    let rows = items
+        .into_iter()
+        .map(|item| match item.kind {
+            ExprKind::List(row) => row,
+            _ => unreachable!("all items are checked to be rows"),
+        })
+        .collect();
+    ExprKind::Matrix(rows)
+}
+// operand wrapped in a group where displaying it next to op_kind would change its meaning
+fn group_operand(op_kind: OperatorKind, operand: &Expr, is_right: bool) -> Expr {
+    let needs_group = match &operand.kind {
+        ExprKind::BinOp {
+            op_kind: inner_op_kind,
+            ..
+        } => {
+            let inner = inner_op_kind.get_precedence();
+            let outer = op_kind.get_precedence();
+            let binds_left = op_kind.associativity() == super::Associativity::Left;
+            inner > outer || (inner == outer && is_right == binds_left)
+        }
+        // -a^b is -(a^b)
+        ExprKind::UnaryOp { .. } => op_kind == OperatorKind::Pow && !is_right,
+        _ => false,
+    };
+    match needs_group {
+        true => operand.spanned(ExprKind::Group(Box::new(operand.clone()))),
+        false => operand.clone(),
+    }
+}
+fn dims(rows: &[Vec<Expr>]) -> (usize, usize) {
+    (rows.len(), rows[0].len())
+}
+fn transpose(rows: &[Vec<Expr>]) -> Rows {
+    (0..rows[0].len())
+        .map(|col| rows.iter().map(|row| row[col].clone()).collect())
+        .collect()
+}
+fn identity(n: usize) -> Rows {
+    identity_values(n)
+        .into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|val| Expr::from(ExprKind::Numeric(val)))
+                .collect()
+        })
+        .collect()
+}
+fn identity_values(n: usize) -> Vec<Vec<f64>> {
+    (0..n)
+        .map(|row| (0..n).map(|col| (row == col) as u8 as f64).collect())
+        .collect()
+}
+// rows without the given row and column
+fn minor(rows: &[Vec<Expr>], row: usize, col: usize) -> Rows {
+    rows.iter()
+        .enumerate()
+        .filter(|(i, _)| *i != row)
+        .map(|(_, entries)| {
+            entries
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != col)
+                .map(|(_, entry)| entry.clone())
+                .collect()
+        })
+        .collect()
+}
+// the entries as numbers, None if any of them is symbolic
+fn numeric_rows(rows: &[Vec<Expr>]) -> Option<Vec<Vec<f64>>> {
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .map(|entry| match entry.kind {
+                    ExprKind::Numeric(val) => Some(val),
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect()
+}
+// gauss-jordan elimination with partial pivoting on the first cols columns of values, other
+// columns are carried along. returns the rank of those columns, and their determinant when they
+// form a square matrix
+fn gauss_jordan(values: &mut [Vec<f64>], cols: usize) -> (usize, f64) {
+    let largest = values
+        .iter()
+        .flat_map(|row| row[..cols].iter())
+        .fold(0.0, |largest: f64, val| largest.max(val.abs()));
+    let tolerance = PIVOT_TOLERANCE * largest;
+    let mut rank = 0;
+    let mut det = 1.0;
+    for col in 0..cols {
+        if rank == values.len() {
+            break;
+        }
+        let pivot = (rank..values.len())
+            .max_by(|&a, &b| values[a][col].abs().total_cmp(&values[b][col].abs()))
+            .expect("range of rows is not empty");
+        if values[pivot][col].abs() <= tolerance {
+            det = 0.0;
+            continue;
+        }
+        if pivot != rank {
+            values.swap(pivot, rank);
+            det = -det;
+        }
+        let pivot_val = values[rank][col];
+        det *= pivot_val;
+        for val in values[rank].iter_mut() {
+            *val /= pivot_val;
+        }
+        let pivot_row = values[rank].clone();
+        for (i, row) in values.iter_mut().enumerate() {
+            let factor = row[col];
+            if i == rank || factor == 0.0 {
+                continue;
+            }
+            for (val, pivot_val) in zip(row.iter_mut(), &pivot_row) {
+                *val -= factor * pivot_val;
+            }
+        }
+        rank += 1;
+    }
+    (rank, det)
+}
@@ -1,52 +1,173 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor};
+use std::rc::Rc;
 use std::string::String;
-use std::{fmt, fs};
+use std::{fmt, mem};
 
-use crate::diag::ParserError;
-use crate::expr::EvalEnv;
+use crate::diag::{LexError, ParserError};
+use crate::expr::{EvalEnv, Program, Stmt};
 use crate::{
     diag::Diagnoster,
-    expr::{Expr, OperatorKind},
+    expr::{constant_value, Expr, ExprKind, OperatorKind},
 };
 
 #[derive(Debug, Clone)]
 pub enum Loc {
-    Repl { line: String, idx: i32 },
-    File { ln: i32, col: i32 },
+    // ln is 1-based and idx 0-based, line holds the text of the current line of the input
+    Repl {
+        line: Rc<str>,
+        ln: usize,
+        idx: usize,
+    },
+    // ln and col are 1-based, line holds the text of the current source line
+    File {
+        path: Rc<str>,
+        line: Rc<str>,
+        ln: usize,
+        col: usize,
+    },
 }
 impl Loc {
     fn increment(&mut self) {
-        *self = match self {
-            Loc::Repl { line, idx } => Loc::Repl {
-                line: line.to_string(),
-                idx: *idx + 1,
-            },
-            Loc::File { ln: _, col: _ } => todo!(),
+        match self {
+            Loc::Repl { idx, .. } => *idx += 1,
+            Loc::File { col, .. } => *col += 1,
+        }
+    }
+    fn next_line(&mut self, next_line: Rc<str>) {
+        match self {
+            Loc::Repl { line, ln, idx } => {
+                *line = next_line;
+                *ln += 1;
+                *idx = 0;
+            }
+            Loc::File { line, ln, col, .. } => {
+                *line = next_line;
+                *ln += 1;
+                *col = 1;
+            }
+        }
+    }
+}
+impl Loc {
+    pub fn line(&self) -> &str {
+        match self {
+            Loc::Repl { line, .. } => line,
+            Loc::File { line, .. } => line,
+        }
+    }
+    // 0-based index of the character within its line
+    pub fn col_idx(&self) -> usize {
+        match self {
+            Loc::Repl { idx, .. } => *idx,
+            Loc::File { col, .. } => col - 1,
+        }
+    }
+    fn is_same_line(&self, other: &Loc) -> bool {
+        match (self, other) {
+            (Loc::Repl { ln, .. }, Loc::Repl { ln: other_ln, .. })
+            | (Loc::File { ln, .. }, Loc::File { ln: other_ln, .. }) => ln == other_ln,
+            _ => true,
+        }
+    }
+}
+impl fmt::Display for Loc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Loc::Repl { ln, idx, .. } => write!(f, "repl:{}:{}", ln, idx + 1),
+            Loc::File { path, ln, col, .. } => write!(f, "{}:{}:{}", path, ln, col),
+        }
+    }
+}
+// source range of a token or expression, end points just past the last character
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub start: Loc,
+    pub end: Loc,
+}
+impl Span {
+    pub fn join(&self, other: &Span) -> Span {
+        Span {
+            start: self.start.clone(),
+            end: other.end.clone(),
+        }
+    }
+    // number of columns to underline on the first line of the span
+    pub fn width(&self) -> usize {
+        if self.start.is_same_line(&self.end) && self.end.col_idx() > self.start.col_idx() {
+            self.end.col_idx() - self.start.col_idx()
+        } else {
+            let line_len = self.start.line().chars().count();
+            line_len.saturating_sub(self.start.col_idx()).max(1)
         }
     }
 }
+impl From<Loc> for Span {
+    // single character span at loc
+    fn from(loc: Loc) -> Self {
+        let mut end = loc.clone();
+        end.increment();
+        Span { start: loc, end }
+    }
+}
 #[derive(Debug, Clone, Copy, PartialEq)]
 
 pub enum TokenKind {
     // TODO: would like to somehow refactor away the need for double definition of operators
     // EOL
     EOL,
+    // end of input, only emitted in lossless mode
+    EOF,
     // parentheses
     OpenParen,
     CloseParen,
+    // brackets, around list literals and indices
+    OpenBracket,
+    CloseBracket,
     //separators
     Comma,
+    // separates the parameters of a lambda from its body
+    Arrow,
+    // ends a statement, like a newline
+    Semicolon,
     // operators
     Equals,
     DoubleEquals,
+    NotEquals,
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
+    And,
+    Or,
     Mult,
     Div,
     Plus,
     Min,
     Pow,
+    // range of numbers, as in 1..10
+    DotDot,
+    // follows the last parameter of a definition, which then takes any remaining arguments
+    Ellipsis,
+    // superscript digits such as ², raise the preceding operand to their value
+    Superscript,
+    // prefix operators
+    Sqrt,
+    Not,
     // operands
     Ident,
     NumLit,
     Bool,
+    // keywords
+    If,
+    Then,
+    Else,
+    Piecewise,
+    Let,
+    In,
+    // malformed input, already reported by the lexer
+    Error,
 }
 
 impl TokenKind {
@@ -56,8 +177,23 @@ impl TokenKind {
         TokenKind::Plus,
         TokenKind::Min,
         TokenKind::Pow,
+        TokenKind::DotDot,
+        TokenKind::Superscript,
         TokenKind::Equals,
         TokenKind::DoubleEquals,
+        TokenKind::NotEquals,
+        TokenKind::Less,
+        TokenKind::LessEquals,
+        TokenKind::Greater,
+        TokenKind::GreaterEquals,
+        TokenKind::And,
+        TokenKind::Or,
+    ];
+    pub const PREFIX_OPERATORS: &'static [TokenKind] = &[
+        TokenKind::Sqrt,
+        TokenKind::Not,
+        TokenKind::Min,
+        TokenKind::Plus,
     ];
     pub const OPERANDS: &'static [TokenKind] =
         &[TokenKind::Ident, TokenKind::NumLit, TokenKind::Bool];
@@ -75,8 +211,15 @@ impl TokenKind {
     fn is_operand(self) -> bool {
         self.is_in(TokenKind::OPERANDS)
     }
-    fn get_precedence(&self) -> i32 {
-        OperatorKind::from_token_kind(self).get_precedence()
+    // binding power of a prefix operator's operand
+    fn prefix_binding_power(self) -> u8 {
+        match self {
+            // only takes the operand that follows, so !a==b is (!a)==b and √x^2 is (√x)^2
+            TokenKind::Not | TokenKind::Sqrt => u8::MAX,
+            // looser than ^ so -2^2 is -(2^2), but tighter than every other operator
+            TokenKind::Min | TokenKind::Plus => OperatorKind::Pow.binding_power().1,
+            _ => panic!("called prefix_binding_power on a {:?}", self),
+        }
     }
 }
 impl fmt::Display for TokenKind {
@@ -84,18 +227,42 @@ impl fmt::Display for TokenKind {
         let output = match &self {
             TokenKind::OpenParen => "(",
             TokenKind::CloseParen => ")",
+            TokenKind::OpenBracket => "[",
+            TokenKind::CloseBracket => "]",
+            TokenKind::DotDot => "..",
+            TokenKind::Ellipsis => "...",
             TokenKind::Comma => ",",
+            TokenKind::Arrow => "->",
+            TokenKind::Semicolon => ";",
             TokenKind::Mult => "*",
             TokenKind::Div => "/",
             TokenKind::Plus => "+",
             TokenKind::Min => "-",
             TokenKind::Pow => "^",
+            TokenKind::Superscript => "superscript",
+            TokenKind::Sqrt => "√",
             TokenKind::Equals => "=",
             TokenKind::Ident => "Ident",
             TokenKind::NumLit => "NumLit",
             TokenKind::Bool => "Bool",
             TokenKind::EOL => "\\n",
-            TokenKind::DoubleEquals => "=",
+            TokenKind::EOF => "end of input",
+            TokenKind::DoubleEquals => "==",
+            TokenKind::NotEquals => "!=",
+            TokenKind::Less => "<",
+            TokenKind::LessEquals => "<=",
+            TokenKind::Greater => ">",
+            TokenKind::GreaterEquals => ">=",
+            TokenKind::And => "&&",
+            TokenKind::Or => "||",
+            TokenKind::Not => "!",
+            TokenKind::If => "if",
+            TokenKind::Then => "then",
+            TokenKind::Else => "else",
+            TokenKind::Piecewise => "piecewise",
+            TokenKind::Let => "let",
+            TokenKind::In => "in",
+            TokenKind::Error => "invalid token",
         };
 
         write!(f, "{}", output)
@@ -103,22 +270,53 @@ impl fmt::Display for TokenKind {
 }
 
 #[derive(Clone, Debug)]
-pub struct Token {
+pub struct Token<'a> {
     pub kind: TokenKind,
-    value: String,
-    pub loc: Loc,
+    // exact source text, borrowed from the input when lexing a &str
+    value: Cow<'a, str>,
+    pub span: Span,
+    // whitespace and comments preceding the token, only kept in lossless mode
+    pub trivia: Vec<Trivia<'a>>,
 }
-impl Token {
-    fn to_value(self) -> f64 {
+impl Token<'_> {
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+    // copies the borrowed text, so the token can outlive the input it was lexed from
+    pub fn into_owned(self) -> Token<'static> {
+        Token {
+            kind: self.kind,
+            value: Cow::Owned(self.value.into_owned()),
+            span: self.span,
+            trivia: self.trivia.into_iter().map(Trivia::into_owned).collect(),
+        }
+    }
+    fn to_value(&self) -> f64 {
         match self.kind {
-            TokenKind::NumLit => self
-                .value
-                .parse::<f64>()
-                .expect("failed to parse NumLit in to_value"),
+            TokenKind::NumLit => {
+                num_lit_value(&self.value).expect("failed to parse NumLit in to_value")
+            }
             _ => panic!("called to_value on a {}", self),
         }
     }
-    fn to_bool(self) -> bool {
+    // exponent denoted by a Superscript token
+    fn superscript_value(&self) -> f64 {
+        match self.kind {
+            TokenKind::Superscript => {
+                let mut value = 0.0;
+                for x in self.value.chars().filter(|&x| x != '⁻') {
+                    value = value * 10.0 + superscript_digit(x).expect("non-digit in superscript");
+                }
+                if self.value.starts_with('⁻') {
+                    -value
+                } else {
+                    value
+                }
+            }
+            _ => panic!("called superscript_value on a {}", self),
+        }
+    }
+    fn to_bool(&self) -> bool {
         match self.kind {
             TokenKind::Bool => self
                 .value
@@ -129,11 +327,12 @@ impl Token {
     }
 }
 
-impl fmt::Display for Token {
+impl fmt::Display for Token<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let output = match &self.kind {
-            TokenKind::Ident => self.value.as_str(),
-            TokenKind::NumLit => self.value.as_str(),
+            TokenKind::Ident => self.value(),
+            TokenKind::NumLit => self.value(),
+            TokenKind::Error => self.value(),
             otherwise => &otherwise.to_string(),
         };
 
@@ -141,52 +340,282 @@ impl fmt::Display for Token {
     }
 }
 
-pub struct Lexer {
-    pub chars: Vec<char>,
-    pub counter: usize,
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TriviaKind {
+    Whitespace,
+    LineComment,
+    BlockComment,
+}
+// source text between tokens, comment text includes the delimiters
+#[derive(Clone, Debug)]
+pub struct Trivia<'a> {
+    pub kind: TriviaKind,
+    pub text: Cow<'a, str>,
+    pub span: Span,
+}
+impl Trivia<'_> {
+    pub fn into_owned(self) -> Trivia<'static> {
+        Trivia {
+            kind: self.kind,
+            text: Cow::Owned(self.text.into_owned()),
+            span: self.span,
+        }
+    }
+}
+
+// characters of the input, read from a BufRead one line at a time, so memory use is bounded by
+// the longest line instead of the size of the input
+struct CharSource {
+    reader: Box<dyn BufRead>,
+    // holds the current line and, when peeking past its end, the next one
+    buffer: Vec<char>,
+    pos: usize,
+    exhausted: bool,
+    // problem hit while reading, taken by the lexer to report it
+    read_error: Option<String>,
+}
+impl CharSource {
+    fn new(reader: Box<dyn BufRead>) -> Self {
+        let mut source = CharSource {
+            reader,
+            buffer: vec![],
+            pos: 0,
+            exhausted: false,
+            read_error: None,
+        };
+        source.fill_line();
+        source
+    }
+    // reads the next line onto the buffer, returns false at the end of the input
+    fn read_line(&mut self) -> bool {
+        if self.exhausted {
+            return false;
+        }
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => self.exhausted = true,
+            Ok(_) => {
+                self.buffer.drain(..self.pos);
+                self.pos = 0;
+                self.buffer.extend(line.chars());
+            }
+            Err(error) => {
+                self.read_error = Some(error.to_string());
+                self.exhausted = true;
+            }
+        }
+        !self.exhausted
+    }
+    // makes sure the line starting at pos is buffered up to and including its newline
+    fn fill_line(&mut self) {
+        while !self.buffer[self.pos..].contains(&'\n') && self.read_line() {}
+    }
+    fn peek_at(&mut self, offset: usize) -> Option<char> {
+        while self.pos + offset >= self.buffer.len() && self.read_line() {}
+        self.buffer.get(self.pos + offset).copied()
+    }
+    fn advance(&mut self) -> Option<char> {
+        let next_char = self.peek_at(0)?;
+        self.pos += 1;
+        if next_char == '\n' {
+            self.fill_line();
+        }
+        Some(next_char)
+    }
+}
+
+// where the lexer reads its characters from
+enum Source<'a> {
+    // borrowed input, token text is sliced out of it without copying
+    Str {
+        input: &'a str,
+        pos: usize,
+        lexeme_start: usize,
+    },
+    // streamed input, token text is collected while reading
+    Stream {
+        chars: CharSource,
+        lexeme: String,
+    },
+}
+impl<'a> Source<'a> {
+    fn peek_at(&mut self, offset: usize) -> Option<char> {
+        match self {
+            Source::Str { input, pos, .. } => input[*pos..].chars().nth(offset),
+            Source::Stream { chars, .. } => chars.peek_at(offset),
+        }
+    }
+    fn advance(&mut self) -> Option<char> {
+        match self {
+            Source::Str { input, pos, .. } => {
+                let next_char = input[*pos..].chars().next()?;
+                *pos += next_char.len_utf8();
+                Some(next_char)
+            }
+            Source::Stream { chars, lexeme } => {
+                let next_char = chars.advance()?;
+                lexeme.push(next_char);
+                Some(next_char)
+            }
+        }
+    }
+    // text of the line starting at the current position
+    fn current_line(&self) -> Rc<str> {
+        match self {
+            Source::Str { input, pos, .. } => {
+                let line = input[*pos..].split('\n').next().unwrap_or_default();
+                line.trim_end_matches('\r').into()
+            }
+            Source::Stream { chars, .. } => line_text(&chars.buffer[chars.pos..]).into(),
+        }
+    }
+    fn take_read_error(&mut self) -> Option<String> {
+        match self {
+            Source::Str { .. } => None,
+            Source::Stream { chars, .. } => chars.read_error.take(),
+        }
+    }
+    // starts collecting the source text of a new token or trivia
+    fn start_lexeme(&mut self) {
+        match self {
+            Source::Str {
+                pos, lexeme_start, ..
+            } => *lexeme_start = *pos,
+            Source::Stream { lexeme, .. } => lexeme.clear(),
+        }
+    }
+    // source text consumed since start_lexeme
+    fn lexeme(&self) -> &str {
+        match self {
+            Source::Str {
+                input,
+                pos,
+                lexeme_start,
+            } => &input[*lexeme_start..*pos],
+            Source::Stream { lexeme, .. } => lexeme,
+        }
+    }
+    fn take_lexeme(&mut self) -> Cow<'a, str> {
+        match self {
+            Source::Str {
+                input,
+                pos,
+                lexeme_start,
+            } => {
+                let input: &'a str = input;
+                Cow::Borrowed(&input[*lexeme_start..*pos])
+            }
+            Source::Stream { lexeme, .. } => Cow::Owned(mem::take(lexeme)),
+        }
+    }
+}
+
+pub struct Lexer<'a> {
+    source: Source<'a>,
     pub current_loc: Loc,
-    peeked_token: Option<Token>,
+    // comments skipped so far, in source order, if keep_comments or keep_trivia is set
+    pub comments: Vec<Trivia<'a>>,
+    pub keep_comments: bool,
+    // lossless mode: whitespace and comments are attached to the following token as trivia,
+    // and the stream ends with an EOF token holding the trailing trivia
+    pub keep_trivia: bool,
+    emitted_eof: bool,
+    peeked_token: Option<Token<'a>>,
+    // problem with the Error token being lexed, handed out along with it
+    error: Option<LexError>,
+    // problems with Error tokens already handed out, kept for take_errors when not reported
+    errors: Vec<LexError>,
+    // whether next_token reports lexical errors, a parser collects them with take_errors instead
+    report_errors: bool,
     diag: Diagnoster,
 }
 
-impl Lexer {
+impl Lexer<'static> {
     pub fn from_string(input: String) -> Self {
+        let source = Source::Stream {
+            chars: CharSource::new(Box::new(Cursor::new(input.into_bytes()))),
+            lexeme: String::new(),
+        };
+        let current_loc = Loc::Repl {
+            line: source.current_line(),
+            ln: 1,
+            idx: 0,
+        };
+        Lexer::with_source(source, current_loc)
+    }
+    pub fn from_file(input_path: &str) -> Option<Self> {
+        let file = File::open(input_path).ok()?;
+        Some(Lexer::from_reader(BufReader::new(file), input_path))
+    }
+    // streams the input, locations are reported as path:ln:col
+    pub fn from_reader(reader: impl BufRead + 'static, path: &str) -> Self {
+        let source = Source::Stream {
+            chars: CharSource::new(Box::new(reader)),
+            lexeme: String::new(),
+        };
+        let current_loc = Loc::File {
+            path: path.into(),
+            line: source.current_line(),
+            ln: 1,
+            col: 1,
+        };
+        Lexer::with_source(source, current_loc)
+    }
+}
+impl<'a> Lexer<'a> {
+    // lexes a borrowed input, token values point into it instead of being copied
+    pub fn new(input: &'a str) -> Self {
+        let source = Source::Str {
+            input,
+            pos: 0,
+            lexeme_start: 0,
+        };
+        let current_loc = Loc::Repl {
+            line: source.current_line(),
+            ln: 1,
+            idx: 0,
+        };
+        Lexer::with_source(source, current_loc)
+    }
+    fn with_source(source: Source<'a>, current_loc: Loc) -> Self {
         Lexer {
-            chars: input.chars().collect(),
-            counter: 0,
-            current_loc: Loc::Repl {
-                line: input.chars().collect(),
-                idx: -1,
-            },
+            source,
+            current_loc,
+            comments: vec![],
+            keep_comments: false,
+            keep_trivia: false,
+            emitted_eof: false,
             peeked_token: None,
-            diag: Diagnoster {},
+            error: None,
+            errors: vec![],
+            report_errors: true,
+            diag: Diagnoster::stderr(),
         }
     }
-    pub fn is_empty(&mut self) -> bool {
-        if let Some(_) = self.peek_char() {
-            false
-        } else {
-            true
-        }
+    // reports lexical errors to diag instead of stderr
+    pub fn with_diag(mut self, diag: Diagnoster) -> Self {
+        self.diag = diag;
+        self
     }
-    fn increment(&mut self) {
-        self.counter += 1;
-        self.current_loc.increment()
+    pub fn is_empty(&mut self) -> bool {
+        self.peek_char().is_none()
     }
     fn peek_char(&mut self) -> Option<char> {
-        if let Some(result) = self.chars.get(self.counter) {
-            Some(*result)
-        } else {
-            None
-        }
+        self.peek_char_at(0)
+    }
+    fn peek_char_at(&mut self, offset: usize) -> Option<char> {
+        self.source.peek_at(offset)
     }
     fn next_char(&mut self) -> Option<char> {
-        if let Some(result) = self.peek_char() {
-            self.increment();
-            Some(result)
+        self.peek_char()?;
+        let consumed = self.source.advance()?;
+        if consumed == '\n' {
+            let next_line = self.source.current_line();
+            self.current_loc.next_line(next_line)
         } else {
-            None
+            self.current_loc.increment()
         }
+        Some(consumed)
     }
     fn next_char_if(&mut self, predicate: impl FnOnce(char) -> bool) -> Option<char> {
         if let Some(next_char) = self.peek_char() {
@@ -196,416 +625,1110 @@ impl Lexer {
         }
         None
     }
-    pub fn next_token(&mut self) -> Option<Token> {
+    // next token for the parser, lexical errors are reported and come out as Error tokens
+    pub fn next_token(&mut self) -> Option<Token<'a>> {
         //TODO: fix is_empty management, causes issues with malformed expressions
         // maybe have a flag for malformed ?
         if let Some(token) = self.peeked_token.take() {
-            Some(token)
-        } else {
-            if let Some(token) = self.token_from_chars() {
-                Some(token)
+            return Some(token);
+        }
+        let token = self.token_from_chars();
+        if let Some(error) = self.error.take() {
+            if self.report_errors {
+                self.diag.report(&error);
             } else {
-                None
+                self.errors.push(error);
             }
         }
+        token
     }
-    pub fn peek_token(&mut self) -> Option<Token> {
-        let token = self.next_token();
-        self.peeked_token = token;
-        self.peeked_token.clone()
+    // lexical errors that were not reported yet, in source order
+    pub fn take_errors(&mut self) -> Vec<LexError> {
+        mem::take(&mut self.errors)
     }
-    fn token_from_chars(&mut self) -> Option<Token> {
+    pub fn peek_token(&mut self) -> Option<&Token<'a>> {
+        if self.peeked_token.is_none() {
+            self.peeked_token = self.next_token();
+        }
+        self.peeked_token.as_ref()
+    }
+    pub fn peek_kind(&mut self) -> Option<TokenKind> {
+        self.peek_token().map(|token| token.kind)
+    }
+    fn token_from_chars(&mut self) -> Option<Token<'a>> {
+        let mut trivia = vec![];
         while let Some(peek_char) = self.peek_char() {
-            if peek_char == ' ' {
-                let _ = self.next_char();
+            let start = self.current_loc.clone();
+            self.source.start_lexeme();
+            if is_whitespace(peek_char) {
+                while self.next_char_if(is_whitespace).is_some() {}
+                if self.keep_trivia {
+                    trivia.push(self.trivia(TriviaKind::Whitespace, start));
+                }
                 continue;
             }
-            let current_loc = self.current_loc.clone();
-            return match peek_char {
-                '\n' => Some(Token {
-                    kind: TokenKind::EOL,
-                    loc: current_loc,
-                    value: self.next_char().unwrap().to_string(),
-                }),
-                '(' => Some(Token {
-                    kind: TokenKind::OpenParen,
-                    loc: current_loc,
-                    value: self.next_char().unwrap().to_string(),
-                }),
-                ')' => Some(Token {
-                    kind: TokenKind::CloseParen,
-                    loc: current_loc,
-                    value: self.next_char().unwrap().to_string(),
-                }),
-                '=' => {
-                    let mut value = self.next_char().unwrap().to_string();
-                    let mut kind = TokenKind::Equals;
-                    if let Some(peek_char) = self.peek_char() {
-                        if peek_char == '=' {
-                            kind = TokenKind::DoubleEquals;
-                            value.push(self.next_char().unwrap());
-                        }
-                    }
-                    Some(Token {
-                        kind,
-                        loc: current_loc,
-                        value,
-                    })
-                }
-                ',' => Some(Token {
-                    kind: TokenKind::Comma,
-                    loc: current_loc,
-                    value: self.next_char().unwrap().to_string(),
-                }),
-                '+' => Some(Token {
-                    kind: TokenKind::Plus,
-                    loc: current_loc,
-                    value: self.next_char().unwrap().to_string(),
-                }),
-                '-' => Some(Token {
-                    kind: TokenKind::Min,
-                    loc: current_loc,
-                    value: self.next_char().unwrap().to_string(),
-                }),
-                '*' => Some(Token {
-                    kind: TokenKind::Mult,
-                    loc: current_loc,
-                    value: self.next_char().unwrap().to_string(),
-                }),
-                '/' => Some(Token {
-                    kind: TokenKind::Div,
-                    loc: current_loc,
-                    value: self.next_char().unwrap().to_string(),
-                }),
-                '^' => Some(Token {
-                    kind: TokenKind::Pow,
-                    loc: current_loc,
-                    value: self.next_char().unwrap().to_string(),
-                }),
-                x if x.is_alphabetic() => {
-                    let mut temp = self.next_char().unwrap().to_string();
-                    while let Some(next_char) = self.next_char_if(|x| x.is_alphanumeric()) {
-                        temp.push(next_char)
-                    }
-                    let mut kind = TokenKind::Ident;
-                    if temp == "true" || temp == "false" {
-                        kind = TokenKind::Bool
-                    }
-                    Some(Token {
-                        kind,
-                        loc: current_loc,
-                        value: temp.clone(),
-                    })
-                }
-                x if x.is_numeric() => {
-                    let mut temp = self.next_char().unwrap().to_string();
-                    let dec_sep = '.';
-                    let mut found_dec_sep = false;
-                    while let Some(next_char) =
-                        self.next_char_if(|x| x.is_numeric() || x == dec_sep)
-                    {
-                        if next_char == dec_sep {
-                            if found_dec_sep {
-                                self.diag.report(ParserError::UnexpectedChar {
-                                    char: next_char,
-                                    loc: self.current_loc.clone(),
-                                });
-                                return None;
-                            } else {
-                                found_dec_sep = true;
-                            }
-                        }
-                        temp.push(next_char)
-                    }
-                    if let Some(peeked_char) = self.peek_char() {
-                        if peeked_char.is_alphabetic() {
-                            self.diag.report(ParserError::UnexpectedChar {
-                                char: peek_char,
-                                loc: current_loc,
-                            });
-                            return None;
-                        }
-                    }
-                    Some(Token {
-                        kind: TokenKind::NumLit,
-                        loc: current_loc,
-                        value: temp.clone(),
-                    })
-                }
-                otherwise => {
-                    self.diag.report(ParserError::UnexpectedChar {
-                        char: otherwise,
-                        loc: current_loc,
+            if peek_char == '#' || (peek_char == '/' && self.peek_char_at(1) == Some('*')) {
+                let Some(kind) = self.lex_comment(&start) else {
+                    return Some(self.token(TokenKind::Error, start, trivia));
+                };
+                let comment = self.trivia(kind, start);
+                if self.keep_comments || self.keep_trivia {
+                    self.comments.push(comment.clone());
+                }
+                if self.keep_trivia {
+                    trivia.push(comment);
+                }
+                continue;
+            }
+            // malformed text becomes an Error token, so lexing can carry on
+            let kind = self
+                .lex_token(peek_char, &start)
+                .unwrap_or(TokenKind::Error);
+            return Some(self.token(kind, start, trivia));
+        }
+        // input that could not be read ends it, with an Error token where it stops
+        if let Some(reason) = self.source.take_read_error() {
+            let start = self.current_loc.clone();
+            self.source.start_lexeme();
+            self.error = Some(LexError::UnreadableInput {
+                reason,
+                span: Span::from(start.clone()),
+            });
+            return Some(self.token(TokenKind::Error, start, trivia));
+        }
+        if self.keep_trivia && !self.emitted_eof {
+            self.emitted_eof = true;
+            self.source.start_lexeme();
+            return Some(self.token(TokenKind::EOF, self.current_loc.clone(), trivia));
+        }
+        None
+    }
+    // token holding the source text consumed since start
+    fn token(&mut self, kind: TokenKind, start: Loc, trivia: Vec<Trivia<'a>>) -> Token<'a> {
+        Token {
+            kind,
+            value: self.source.take_lexeme(),
+            span: Span {
+                start,
+                end: self.current_loc.clone(),
+            },
+            trivia,
+        }
+    }
+    fn trivia(&mut self, kind: TriviaKind, start: Loc) -> Trivia<'a> {
+        Trivia {
+            kind,
+            text: self.source.take_lexeme(),
+            span: Span {
+                start,
+                end: self.current_loc.clone(),
+            },
+        }
+    }
+    // lexes the token starting with peek_char
+    fn lex_token(&mut self, peek_char: char, start: &Loc) -> Option<TokenKind> {
+        self.next_char();
+        let kind = match peek_char {
+            '\n' => TokenKind::EOL,
+            '(' => TokenKind::OpenParen,
+            ')' => TokenKind::CloseParen,
+            '[' => TokenKind::OpenBracket,
+            ']' => TokenKind::CloseBracket,
+            '.' => {
+                if self.next_char_if(|x| x == '.').is_none() {
+                    return self.lex_error(LexError::UnexpectedChar {
+                        char: '.',
+                        span: Span::from(start.clone()),
                     });
-                    None
                 }
+                match self.next_char_if(|x| x == '.') {
+                    Some(_) => TokenKind::Ellipsis,
+                    None => TokenKind::DotDot,
+                }
+            }
+            '=' => match self.next_char_if(|x| x == '=') {
+                Some(_) => TokenKind::DoubleEquals,
+                None => TokenKind::Equals,
+            },
+            '<' | '>' | '!' => {
+                let followed_by_equals = self.next_char_if(|x| x == '=').is_some();
+                match (peek_char, followed_by_equals) {
+                    ('<', false) => TokenKind::Less,
+                    ('<', true) => TokenKind::LessEquals,
+                    ('>', false) => TokenKind::Greater,
+                    ('>', true) => TokenKind::GreaterEquals,
+                    ('!', false) => TokenKind::Not,
+                    _ => TokenKind::NotEquals,
+                }
+            }
+            '&' | '|' => {
+                if self.next_char_if(|x| x == peek_char).is_none() {
+                    return self.lex_error(LexError::UnexpectedChar {
+                        char: peek_char,
+                        span: Span::from(start.clone()),
+                    });
+                }
+                match peek_char {
+                    '&' => TokenKind::And,
+                    _ => TokenKind::Or,
+                }
+            }
+            ',' => TokenKind::Comma,
+            ';' => TokenKind::Semicolon,
+            '+' => TokenKind::Plus,
+            '-' | '−' => match self.next_char_if(|x| x == '>') {
+                Some(_) => TokenKind::Arrow,
+                None => TokenKind::Min,
+            },
+            '→' => TokenKind::Arrow,
+            '*' | '×' | '·' => TokenKind::Mult,
+            '/' | '÷' => TokenKind::Div,
+            '^' => TokenKind::Pow,
+            '√' => TokenKind::Sqrt,
+            x if superscript_digit(x).is_some() || x == '⁻' => {
+                while self
+                    .next_char_if(|x| superscript_digit(x).is_some())
+                    .is_some()
+                {}
+                if self.source.lexeme() == "⁻" {
+                    return self.lex_error(LexError::UnexpectedChar {
+                        char: '⁻',
+                        span: Span {
+                            start: start.clone(),
+                            end: self.current_loc.clone(),
+                        },
+                    });
+                }
+                TokenKind::Superscript
+            }
+            x if x.is_alphabetic() => {
+                // identifiers can contain letters and digits, including subscripts like θ₁,
+                // but superscripts are left for the Superscript token
+                while self
+                    .next_char_if(|x| x.is_alphanumeric() && superscript_digit(x).is_none())
+                    .is_some()
+                {}
+                match self.source.lexeme() {
+                    "true" | "false" => TokenKind::Bool,
+                    "if" => TokenKind::If,
+                    "then" => TokenKind::Then,
+                    "else" => TokenKind::Else,
+                    "piecewise" => TokenKind::Piecewise,
+                    "let" => TokenKind::Let,
+                    "in" => TokenKind::In,
+                    _ => TokenKind::Ident,
+                }
+            }
+            x if x.is_ascii_digit() => {
+                self.lex_num_lit(x, start)?;
+                TokenKind::NumLit
+            }
+            otherwise => {
+                return self.lex_error(LexError::UnexpectedChar {
+                    char: otherwise,
+                    span: Span::from(start.clone()),
+                });
+            }
+        };
+        Some(kind)
+    }
+    // lexes a line comment up to the next newline, or a (possibly nested) block comment
+    fn lex_comment(&mut self, start: &Loc) -> Option<TriviaKind> {
+        if self.peek_char() == Some('#') {
+            while self.next_char_if(|x| x != '\n').is_some() {}
+            return Some(TriviaKind::LineComment);
+        }
+        let mut depth = 0;
+        loop {
+            match (self.peek_char(), self.peek_char_at(1)) {
+                (Some('/'), Some('*')) => depth += 1,
+                (Some('*'), Some('/')) => depth -= 1,
+                (Some(_), _) => {
+                    self.next_char();
+                    continue;
+                }
+                (None, _) => {
+                    return self.lex_error(LexError::UnterminatedComment {
+                        span: Span {
+                            start: start.clone(),
+                            end: self.current_loc.clone(),
+                        },
+                    });
+                }
+            }
+            self.next_char();
+            self.next_char();
+            if depth == 0 {
+                return Some(TriviaKind::BlockComment);
+            }
+        }
+    }
+    // lexes the rest of a numeric literal such as 12, 1_000.5, 6.022e23, 1e-9, 0x1F, 0b1010 or
+    // 0o17, whose first digit was already consumed
+    fn lex_num_lit(&mut self, first_digit: char, start: &Loc) -> Option<()> {
+        let mut literal = first_digit.to_string();
+        let radix = match (first_digit, self.peek_char()) {
+            ('0', Some('x' | 'X')) => 16,
+            ('0', Some('b' | 'B')) => 2,
+            ('0', Some('o' | 'O')) => 8,
+            _ => 10,
+        };
+        if radix != 10 {
+            literal.push(self.next_char().unwrap());
+            if !self.lex_digits(&mut literal, radix, start)? {
+                let reason = format!("expected digits after '{}'", literal);
+                return self.report_num_lit(literal, reason, start);
+            }
+        } else {
+            self.lex_digits(&mut literal, radix, start)?;
+            // a second dot makes it the end of the literal and the start of a range
+            if self.peek_char() == Some('.') && self.peek_char_at(1) != Some('.') {
+                literal.push(self.next_char().unwrap());
+                self.lex_digits(&mut literal, radix, start)?;
+            }
+            // e only starts an exponent when digits follow, otherwise 2e is 2 times e
+            let starts_exponent = match (self.peek_char(), self.peek_char_at(1)) {
+                (Some('e' | 'E'), Some('+' | '-')) => {
+                    self.peek_char_at(2).is_some_and(|x| x.is_ascii_digit())
+                }
+                (Some('e' | 'E'), Some(x)) => x.is_ascii_digit(),
+                _ => false,
             };
+            if starts_exponent {
+                literal.push(self.next_char().unwrap());
+                if let Some(sign @ ('+' | '-')) = self.peek_char() {
+                    literal.push(sign);
+                    self.next_char();
+                }
+                self.lex_digits(&mut literal, radix, start)?;
+            }
         }
+        if let Some(next_char) = self.peek_char() {
+            let reason = match next_char {
+                '.' if self.peek_char_at(1) == Some('.') => None,
+                '.' if radix != 10 => Some("only decimal literals can have a fractional part"),
+                '.' => Some("found a second decimal separator"),
+                '_' => Some("digit separators can only appear between digits"),
+                x if x.is_ascii_digit() => Some("digit is out of range for the literal's base"),
+                // decimal literals can be followed by a letter, as in 2x
+                x if radix != 10 && x.is_alphanumeric() && superscript_digit(x).is_none() => {
+                    Some("literal is directly followed by a letter")
+                }
+                _ => None,
+            };
+            if let Some(reason) = reason {
+                return self.report_num_lit(literal, reason.to_string(), start);
+            }
+        }
+        if num_lit_value(&literal).is_none() {
+            return self.lex_error(LexError::InvalidNumLit {
+                literal,
+                reason: "value is too large".to_string(),
+                span: Span {
+                    start: start.clone(),
+                    end: self.current_loc.clone(),
+                },
+            });
+        }
+        Some(())
+    }
+    // pushes digits in the given radix and '_' separators between them onto literal,
+    // returns whether any digit was found
+    fn lex_digits(&mut self, literal: &mut String, radix: u32, start: &Loc) -> Option<bool> {
+        let mut found_digit = false;
+        while let Some(next_char) = self.peek_char() {
+            if next_char == '_' {
+                let follows_digit = literal.chars().last().is_some_and(|x| x.is_digit(radix));
+                let precedes_digit = self.peek_char_at(1).is_some_and(|x| x.is_digit(radix));
+                if !follows_digit || !precedes_digit {
+                    return self.report_num_lit(
+                        literal.clone(),
+                        "digit separators can only appear between digits".to_string(),
+                        start,
+                    );
+                }
+            } else if !next_char.is_digit(radix) {
+                break;
+            } else {
+                found_digit = true;
+            }
+            literal.push(self.next_char().unwrap());
+        }
+        Some(found_digit)
+    }
+    // reports a malformed literal starting at start and skips the rest of it, so it becomes a
+    // single Error token spanning the whole literal
+    fn report_num_lit<T>(&mut self, literal: String, reason: String, start: &Loc) -> Option<T> {
+        while self
+            .next_char_if(|x| x.is_alphanumeric() || x == '.' || x == '_')
+            .is_some()
+        {}
+        self.lex_error(LexError::InvalidNumLit {
+            literal,
+            reason,
+            span: Span {
+                start: start.clone(),
+                end: self.current_loc.clone(),
+            },
+        })
+    }
+    // records the problem with the Error token being lexed, which is reported or handed out
+    // along with it
+    fn lex_error<T>(&mut self, error: LexError) -> Option<T> {
+        self.error = Some(error);
         None
     }
     pub fn expect_token_kinds(
         &mut self,
         expected: &[TokenKind],
         while_doing: String,
-    ) -> Option<Token> {
-        if let Some(token) = self.peek_token() {
-            if token.kind.is_in(expected) {
-                return self.next_token();
-            }
+    ) -> Option<Token<'a>> {
+        if self.peek_kind().is_some_and(|kind| kind.is_in(expected)) {
+            return self.next_token();
+        }
+        if let Some(error) = self.expected_token_error(expected, while_doing) {
+            self.diag.report(&error);
         }
+        None
+    }
+    // error for the peeked token not being one of expected, None for an Error token since
+    // lexical errors are handled when the Error token is emitted
+    fn expected_token_error(
+        &mut self,
+        expected: &[TokenKind],
+        while_doing: String,
+    ) -> Option<ParserError> {
         let found = self.peek_token();
-        self.diag.report(ParserError::ExpectedToken {
+        if found.is_some_and(|token| token.kind == TokenKind::Error) {
+            return None;
+        }
+        let found = found.cloned().map(Token::into_owned);
+        let span = match &found {
+            Some(token) => token.span.clone(),
+            None => Span::from(self.current_loc.clone()),
+        };
+        Some(ParserError::ExpectedToken {
             expected: expected.to_vec(),
             found,
             while_doing,
-            loc: self.current_loc.clone(),
-        });
-        None
+            span,
+        })
     }
-    fn drop_token(&mut self) {
-        let _ = self.next_token();
+}
+// yields lexical errors instead of reporting them, for tools that drive the lexer directly
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token<'a>, LexError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        // a peeked token had its error handled by next_token already
+        if let Some(token) = self.peeked_token.take() {
+            return Some(Ok(token));
+        }
+        let token = self.token_from_chars()?;
+        match self.error.take() {
+            Some(error) => Some(Err(error)),
+            None => Some(Ok(token)),
+        }
     }
 }
 
-pub struct Parser {
-    lexer: Lexer,
-    stash: Vec<Expr>,
+pub struct Parser<'a> {
+    lexer: Lexer<'a>,
     diag: Diagnoster,
+    // nesting of parentheses and function arguments around the current expression
     depth: i32,
+    // parameters of the function definition whose body is being parsed
+    params: Vec<String>,
+    // errors found since the current statement started, lexical ones included
+    errors: Vec<ParserError>,
 }
-impl Parser {
+impl Parser<'static> {
     pub fn from_string(input: String) -> Self {
+        Parser::from_lexer(Lexer::from_string(input))
+    }
+    pub fn from_file(input_path: &str) -> Option<Self> {
+        Some(Parser::from_lexer(Lexer::from_file(input_path)?))
+    }
+    pub fn from_reader(reader: impl BufRead + 'static, path: &str) -> Self {
+        Parser::from_lexer(Lexer::from_reader(reader, path))
+    }
+}
+impl<'a> Parser<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Parser::from_lexer(Lexer::new(input))
+    }
+    fn from_lexer(mut lexer: Lexer<'a>) -> Self {
+        lexer.report_errors = false;
         Parser {
-            lexer: Lexer::from_string(input),
-            stash: vec![],
-            diag: Diagnoster {},
+            diag: lexer.diag.clone(),
+            lexer,
             depth: 0,
+            params: vec![],
+            errors: vec![],
         }
     }
-    pub fn from_file(input_path: &str) -> Option<Self> {
-        let input = fs::read_to_string(input_path).ok()?;
-        let input = input.replace("\r", "");
-        Some(Parser {
-            lexer: Lexer::from_string(input),
-            stash: vec![],
-            diag: Diagnoster {},
-            depth: 0,
-        })
+    // reports errors to diag instead of stderr
+    pub fn with_diag(mut self, diag: Diagnoster) -> Self {
+        self.lexer.diag = diag.clone();
+        self.diag = diag;
+        self
+    }
+    // reports and records error, after the lexical errors found before it
+    fn error(&mut self, error: ParserError) {
+        self.take_lex_errors();
+        self.diag.report(&error);
+        self.errors.push(error);
+    }
+    fn take_lex_errors(&mut self) {
+        for error in self.lexer.take_errors() {
+            let error = ParserError::Lex(error);
+            self.diag.report(&error);
+            self.errors.push(error);
+        }
+    }
+    // records the peeked token as unexpected
+    fn report_unexpected(&mut self, while_doing: String) {
+        if let Some(found) = self.lexer.peek_token() {
+            let found = found.clone().into_owned();
+            self.error(ParserError::UnexpectedToken { found, while_doing });
+        }
+    }
+    // next token if it is one of expected, records an error otherwise
+    fn expect(&mut self, expected: &[TokenKind], while_doing: String) -> Option<Token<'a>> {
+        if self
+            .lexer
+            .peek_kind()
+            .is_some_and(|kind| kind.is_in(expected))
+        {
+            return self.lexer.next_token();
+        }
+        if let Some(error) = self.lexer.expected_token_error(expected, while_doing) {
+            self.error(error);
+        }
+        None
     }
 
+    // parses an operand: a literal, variable, function call, group, list, tuple or prefix operator
+    // application
     fn parse_operand(&mut self, eval_env: &EvalEnv) -> Option<Expr> {
-        let token = self.lexer.expect_token_kinds(
-            &[&[TokenKind::OpenParen], TokenKind::OPERANDS].concat(),
+        let token = self.expect(
+            &[
+                &[
+                    TokenKind::OpenParen,
+                    TokenKind::OpenBracket,
+                    TokenKind::If,
+                    TokenKind::Piecewise,
+                    TokenKind::Let,
+                ],
+                TokenKind::OPERANDS,
+                TokenKind::PREFIX_OPERATORS,
+            ]
+            .concat(),
             "while parsing operand".to_string(),
         )?;
         match token.kind {
             TokenKind::Ident => {
-                if let Some(next_token) = self.lexer.peek_token() {
-                    if next_token.kind == TokenKind::OpenParen {
-                        return self.parse_functor(token.value, eval_env);
-                    }
+                if self.lexer.peek_kind() == Some(TokenKind::Arrow) {
+                    let param = Expr::new(
+                        ExprKind::Variable(token.value.to_string()),
+                        token.span.clone(),
+                    );
+                    return self.parse_lambda(token.span, vec![param], eval_env);
+                }
+                if self.lexer.peek_kind() == Some(TokenKind::OpenParen)
+                    && self.is_functor(&token.value, eval_env)
+                {
+                    return self.parse_functor(token, eval_env);
                 }
-                Some(Expr::Variable(token.value))
+                Some(Expr::new(
+                    ExprKind::Variable(token.value.into_owned()),
+                    token.span,
+                ))
+            }
+            TokenKind::NumLit => Some(Expr::new(ExprKind::Numeric(token.to_value()), token.span)),
+            TokenKind::Bool => Some(Expr::new(ExprKind::Bool(token.to_bool()), token.span)),
+            TokenKind::Not | TokenKind::Sqrt | TokenKind::Min | TokenKind::Plus => {
+                let operand = self.parse_expr(eval_env, token.kind.prefix_binding_power())?;
+                let span = match &operand.span {
+                    Some(operand_span) => token.span.join(operand_span),
+                    None => token.span,
+                };
+                let kind = match token.kind {
+                    TokenKind::Sqrt => ExprKind::Fun {
+                        name: "sqrt".to_string(),
+                        params: vec![operand],
+                    },
+                    prefix => ExprKind::UnaryOp {
+                        op_kind: OperatorKind::from_token_kind(&prefix),
+                        operand: Box::new(operand),
+                    },
+                };
+                Some(Expr::new(kind, span))
             }
-            TokenKind::NumLit => Some(Expr::Numeric(token.to_value())),
-            TokenKind::Bool => Some(Expr::Bool(token.to_bool())),
             TokenKind::OpenParen => {
-                let operand = self.parse_impl(eval_env, false)?;
-                let _ = self.lexer.expect_token_kinds(
+                self.depth += 1;
+                let mut operands = vec![self.parse_expr(eval_env, 0)?];
+                // several expressions between parentheses are a tuple or the parameters of a lambda
+                while self.lexer.peek_kind() == Some(TokenKind::Comma) {
+                    self.lexer.next_token();
+                    operands.push(self.parse_expr(eval_env, 0)?);
+                }
+                self.depth -= 1;
+                let close_paren = self.expect(
                     &[TokenKind::CloseParen],
                     "while parsing expression between parentheses".to_string(),
                 )?;
-                Some(Expr::Group(Box::new(operand)))
+                if self.lexer.peek_kind() == Some(TokenKind::Arrow) {
+                    return self.parse_lambda(token.span, operands, eval_env);
+                }
+                let kind = match operands.len() {
+                    1 => ExprKind::Group(Box::new(operands.remove(0))),
+                    _ => ExprKind::Tuple(operands),
+                };
+                Some(Expr::new(kind, token.span.join(&close_paren.span)))
+            }
+            TokenKind::OpenBracket => {
+                let mut items = vec![];
+                if self.lexer.peek_kind() != Some(TokenKind::CloseBracket) {
+                    self.depth += 1;
+                    items.push(self.parse_expr(eval_env, 0)?);
+                    while self.lexer.peek_kind() == Some(TokenKind::Comma) {
+                        self.lexer.next_token();
+                        items.push(self.parse_expr(eval_env, 0)?);
+                    }
+                    self.depth -= 1;
+                }
+                let close_bracket =
+                    self.expect(&[TokenKind::CloseBracket], "while parsing list".to_string())?;
+                Some(Expr::new(
+                    ExprKind::List(items),
+                    token.span.join(&close_bracket.span),
+                ))
             }
+            TokenKind::If => self.parse_if(token, eval_env),
+            TokenKind::Piecewise => self.parse_piecewise(token, eval_env),
+            TokenKind::Let => self.parse_let(token, eval_env),
             _ => None,
         }
     }
-    fn parse_binop(&mut self, left: Expr, eval_env: &EvalEnv) -> Option<Expr> {
-        let operator = self.lexer.expect_token_kinds(
-            TokenKind::OPERATORS,
-            "while parsing binary operator".to_string(),
+    // parses the rest of `params -> body`, the body extends as far as possible
+    fn parse_lambda(&mut self, start: Span, params: Vec<Expr>, eval_env: &EvalEnv) -> Option<Expr> {
+        self.expect(
+            &[TokenKind::Arrow],
+            "after parameters of lambda".to_string(),
         )?;
-        if operator.kind == TokenKind::Equals {
-            match left {
-                Expr::Variable(_) => (),
-                Expr::Fun { name: _, params: _ } => (),
+        let mut names = vec![];
+        for param in params {
+            match param.kind {
+                ExprKind::Variable(name) => names.push(name),
                 _ => {
-                    self.diag.report(ParserError::InvalidExpr {
-                        loc: operator.loc,
-                        found: Box::new(left),
-                        reason: "can only assign values to a variable".to_string(),
+                    self.error(ParserError::InvalidFuncParam {
+                        found: Box::new(param),
+                        while_doing: "parsing lambda".to_string(),
                     });
                     return None;
                 }
             }
         }
-        let right = self.parse_operand(eval_env)?;
-        while let Some(token) = self.lexer.peek_token() {
-            match token.kind {
-                TokenKind::CloseParen => break,
+        let outer_params = self.params.clone();
+        self.params.extend(names.iter().cloned());
+        self.depth += 1;
+        let body = self.parse_expr(eval_env, 0)?;
+        self.depth -= 1;
+        self.params = outer_params;
+        let span = match &body.span {
+            Some(body_span) => start.join(body_span),
+            None => start,
+        };
+        let kind = ExprKind::Lambda {
+            params: names,
+            body: Box::new(body),
+        };
+        Some(Expr::new(kind, span))
+    }
+    // parses the rest of `if cond then a else b`, the else branch extends as far as possible
+    fn parse_if(&mut self, if_token: Token, eval_env: &EvalEnv) -> Option<Expr> {
+        // like between parentheses, an assignment can't be part of a condition or branch
+        self.depth += 1;
+        let cond = self.parse_expr(eval_env, 0)?;
+        self.expect(
+            &[TokenKind::Then],
+            "while parsing if expression".to_string(),
+        )?;
+        let then = self.parse_expr(eval_env, 0)?;
+        self.expect(
+            &[TokenKind::Else],
+            "while parsing if expression".to_string(),
+        )?;
+        let otherwise = self.parse_expr(eval_env, 0)?;
+        self.depth -= 1;
+        let span = match &otherwise.span {
+            Some(otherwise_span) => if_token.span.join(otherwise_span),
+            None => if_token.span,
+        };
+        let kind = ExprKind::If {
+            cond: Box::new(cond),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+        };
+        Some(Expr::new(kind, span))
+    }
+    // parses the rest of `let name = value in body`, the body extends as far as possible
+    fn parse_let(&mut self, let_token: Token, eval_env: &EvalEnv) -> Option<Expr> {
+        let name = self.expect(&[TokenKind::Ident], "while parsing let".to_string())?;
+        let name = name.value.into_owned();
+        self.expect(&[TokenKind::Equals], "after name bound by let".to_string())?;
+        // like between parentheses, an assignment can't be part of the value or body
+        self.depth += 1;
+        let value = self.parse_expr(eval_env, 0)?;
+        self.expect(&[TokenKind::In], "after value bound by let".to_string())?;
+        // the name may be bound to a function, so calling it is allowed in the body
+        let outer_params = self.params.clone();
+        self.params.push(name.clone());
+        let body = self.parse_expr(eval_env, 0)?;
+        self.params = outer_params;
+        self.depth -= 1;
+        let span = match &body.span {
+            Some(body_span) => let_token.span.join(body_span),
+            None => let_token.span,
+        };
+        let kind = ExprKind::Let {
+            name,
+            value: Box::new(value),
+            body: Box::new(body),
+        };
+        Some(Expr::new(kind, span))
+    }
+    // parses the rest of `piecewise(a if cond, b if cond, c)`, only the last branch can leave out
+    // its condition, it then applies when none of the others do
+    fn parse_piecewise(&mut self, piecewise_token: Token, eval_env: &EvalEnv) -> Option<Expr> {
+        self.expect(
+            &[TokenKind::OpenParen],
+            "while parsing piecewise".to_string(),
+        )?;
+        self.depth += 1;
+        let mut branches = vec![];
+        let mut otherwise = None;
+        let close_paren = loop {
+            let value = self.parse_expr(eval_env, 0)?;
+            if self.lexer.peek_kind() != Some(TokenKind::If) {
+                otherwise = Some(Box::new(value));
+                break self.expect(
+                    &[TokenKind::If, TokenKind::CloseParen],
+                    "after a piecewise branch without condition".to_string(),
+                )?;
+            }
+            self.lexer.next_token();
+            branches.push((self.parse_expr(eval_env, 0)?, value));
+            let token = self.expect(
+                &[TokenKind::CloseParen, TokenKind::Comma],
+                "while parsing piecewise".to_string(),
+            )?;
+            if token.kind == TokenKind::CloseParen {
+                break token;
+            }
+        };
+        self.depth -= 1;
+        let span = piecewise_token.span.join(&close_paren.span);
+        Some(Expr::new(
+            ExprKind::Piecewise {
+                branches,
+                otherwise,
+            },
+            span,
+        ))
+    }
+    // Pratt parser: parses an expression made of operators whose left binding power is at least
+    // min_bp, so tighter operators end up deeper in the tree
+    fn parse_expr(&mut self, eval_env: &EvalEnv, min_bp: u8) -> Option<Expr> {
+        let mut left = self.parse_operand(eval_env)?;
+        while let Some(kind) = self.lexer.peek_kind() {
+            match kind {
+                TokenKind::EOL
+                | TokenKind::EOF
+                | TokenKind::Semicolon
+                | TokenKind::CloseParen
+                | TokenKind::CloseBracket
+                | TokenKind::Comma
+                | TokenKind::Ellipsis
+                | TokenKind::If
+                | TokenKind::Then
+                | TokenKind::Else
+                | TokenKind::In => break,
+                TokenKind::Error => return None,
+                // indexing binds tighter than any operator, so -xs[0] is -(xs[0])
+                TokenKind::OpenBracket => {
+                    self.lexer.next_token();
+                    self.depth += 1;
+                    let index = self.parse_expr(eval_env, 0)?;
+                    self.depth -= 1;
+                    let close_bracket = self.expect(
+                        &[TokenKind::CloseBracket],
+                        "while parsing index".to_string(),
+                    )?;
+                    let span = match &left.span {
+                        Some(left_span) => left_span.join(&close_bracket.span),
+                        None => close_bracket.span,
+                    };
+                    left = Expr::new(
+                        ExprKind::Index {
+                            target: Box::new(left),
+                            index: Box::new(index),
+                        },
+                        span,
+                    );
+                }
                 x if x.is_operator() => {
-                    if x.get_precedence() < operator.kind.get_precedence() {
-                        match self.stash.pop() {
-                            Some(prev_expr) => {
-                                let temp_right = self.parse_binop(prev_expr, eval_env)?;
-                                self.stash.push(temp_right)
-                            }
-                            None => {
-                                let temp_right = self.parse_binop(right.clone(), eval_env)?;
-                                self.stash.push(temp_right)
-                            }
-                        }
-                    } else {
+                    let (left_bp, right_bp) = OperatorKind::from_token_kind(&x).binding_power();
+                    if left_bp < min_bp {
+                        break;
+                    }
+                    let operator = self.lexer.next_token()?;
+                    left = self.parse_binop(left, operator, right_bp, eval_env)?;
+                }
+                // juxtaposition such as 2x, 3(a+b) or 2 π r is an implicit multiplication. it binds
+                // tighter than * and / so 1/2x is 1/(2x), but looser than ^ so 2x^2 is 2(x^2)
+                TokenKind::Ident | TokenKind::OpenParen | TokenKind::Sqrt => {
+                    let (_, mult_right_bp) = OperatorKind::Mult.binding_power();
+                    let (left_bp, right_bp) = (mult_right_bp, mult_right_bp + 1);
+                    if left_bp < min_bp {
                         break;
                     }
+                    let right = self.parse_expr(eval_env, right_bp)?;
+                    let span = span_between(&left, &right);
+                    left = Expr {
+                        kind: ExprKind::BinOp {
+                            op_kind: OperatorKind::Mult,
+                            left: Box::new(left),
+                            right: Box::new(right),
+                        },
+                        span,
+                    };
                 }
-                TokenKind::EOL => break,
                 _ => {
-                    self.diag.report(ParserError::UnexpectedToken {
-                        found: token,
-                        while_doing: "while parsing binary operator".to_string(),
-                    });
+                    self.report_unexpected(format!("Parsing after expression {}", left));
                     return None;
                 }
             }
         }
-        match self.stash.pop() {
-            Some(right_expr) => {
-                return Some(Expr::BinOp {
-                    op_kind: OperatorKind::from_token_kind(&operator.kind),
-                    left: Box::new(left),
-                    right: Box::new(right_expr),
+        Some(left)
+    }
+    // parses the right operand of operator, binding operators of at least right_bp
+    fn parse_binop(
+        &mut self,
+        left: Expr,
+        operator: Token,
+        right_bp: u8,
+        eval_env: &EvalEnv,
+    ) -> Option<Expr> {
+        if operator.kind == TokenKind::Equals {
+            if self.depth != 0 {
+                self.error(ParserError::UnexpectedToken {
+                    found: operator.into_owned(),
+                    while_doing: "while not parsing a top-level operator.\
+                     Equals is only allowed as the main expression, not in a subexpression"
+                        .to_string(),
                 });
+                return None;
             }
-            None => {
-                return Some(Expr::BinOp {
-                    op_kind: OperatorKind::from_token_kind(&operator.kind),
-                    left: Box::new(left),
-                    right: Box::new(right),
-                });
+            match left.kind {
+                ExprKind::Variable(_) => (),
+                ExprKind::Fun { name: _, params: _ } => (),
+                _ => {
+                    self.error(ParserError::InvalidExpr {
+                        span: left.span.clone().unwrap_or(operator.span),
+                        found: Box::new(left),
+                        reason: "can only assign values to a variable".to_string(),
+                    });
+                    return None;
+                }
             }
         }
+        // the names in the parameters of a function definition are values in its body, which may
+        // be functions, so x(x+1) parses as a call that call_value multiplies when x is a number
+        if let (TokenKind::Equals, ExprKind::Fun { name: _, params }) = (operator.kind, &left.kind)
+        {
+            self.params = params
+                .iter()
+                .flat_map(Expr::bound_names)
+                .map(str::to_string)
+                .collect();
+        }
+        let right = match operator.kind {
+            // a superscript is a postfix operator carrying its own exponent
+            TokenKind::Superscript => Expr::new(
+                ExprKind::Numeric(operator.superscript_value()),
+                operator.span.clone(),
+            ),
+            _ => self.parse_expr(eval_env, right_bp)?,
+        };
+        if operator.kind == TokenKind::Equals {
+            self.params.clear();
+        }
+        let span = span_between(&left, &right);
+        Some(Expr {
+            kind: ExprKind::BinOp {
+                op_kind: OperatorKind::from_token_kind(&operator.kind),
+                left: Box::new(left),
+                right: Box::new(right),
+            },
+            span,
+        })
     }
-    fn parse_functor(&mut self, name: String, eval_env: &EvalEnv) -> Option<Expr> {
-        let _ = self
-            .lexer
-            .expect_token_kinds(&[TokenKind::OpenParen], "while parsing functor".to_string())?;
+    // whether name followed by parentheses is a call rather than a multiplication, which is the
+    // case unless name holds a number or boolean. the variables of a program being parsed hold
+    // their unevaluated values, so anything else may turn out to be a function. calls multiply
+    // when evaluated with a value instead
+    fn is_functor(&self, name: &str, eval_env: &EvalEnv) -> bool {
+        if eval_env.funcs.contains_key(name) || self.params.iter().any(|param| param == name) {
+            return true;
+        }
+        match eval_env.vars.get(name) {
+            Some(val) => !val.is_num() && !val.is_bool(),
+            None => constant_value(name).is_none(),
+        }
+    }
+    fn parse_functor(&mut self, name_token: Token, eval_env: &EvalEnv) -> Option<Expr> {
+        let name = name_token.value.into_owned();
+        let _ = self.expect(&[TokenKind::OpenParen], "while parsing functor".to_string())?;
+        self.depth += 1;
         let mut args = vec![];
-        while let Some(peeked_token) = self.lexer.peek_token() {
-            if let Some(arg) = self.parse_impl(eval_env, true) {
-                args.push(arg);
-                let token = self.lexer.expect_token_kinds(
-                    &[TokenKind::CloseParen, TokenKind::Comma],
-                    "while parsing functor".to_string(),
-                )?;
-                match token.kind {
-                    TokenKind::CloseParen => {
-                        if let Some(func_def) = eval_env.funcs.get(&name) {
-                            if let Some(token) = self.lexer.peek_token() {
-                                if token.kind == TokenKind::Equals {
-                                    return Some(Expr::Fun { name, params: args });
-                                }
-                            }
-                            if let Expr::BinOp {
-                                op_kind: _,
-                                left,
-                                right: _,
-                            } = *func_def.clone()
-                            {
-                                if let Expr::Fun { name, params } = *left {
-                                    if params.len() != args.len() {
-                                        self.diag.report(ParserError::InvalidExpr {
-                                            loc: token.loc,
-                                            found: Box::new(Expr::Fun { name:name.clone(), params: args }),
-                                            reason: format!("function {} is already defined as {}, and the number of arguments doesn't match",name,func_def),
-                                        });
-                                        return None;
-                                    }
-                                }
-                            }
-                        }
-                        return Some(Expr::Fun { name, params: args });
-                    }
-                    TokenKind::Comma => continue,
-                    _ => panic!("found not comma or close paren while expecting them"),
+        // an argument stops before =, which names it in a call or gives a parameter a default
+        let (_, equals_right_bp) = OperatorKind::Equals.binding_power();
+        let close_paren = loop {
+            // f() passes no arguments, to a variadic parameter or parameters with defaults
+            if args.is_empty() && self.lexer.peek_kind() == Some(TokenKind::CloseParen) {
+                break self.lexer.next_token()?;
+            }
+            let mut arg = self.parse_expr(eval_env, equals_right_bp)?;
+            match self.lexer.peek_kind() {
+                Some(TokenKind::Equals) if arg.is_var() => {
+                    self.lexer.next_token();
+                    let value = self.parse_expr(eval_env, 0)?;
+                    arg = Expr {
+                        span: span_between(&arg, &value),
+                        kind: ExprKind::BinOp {
+                            op_kind: OperatorKind::Equals,
+                            left: Box::new(arg),
+                            right: Box::new(value),
+                        },
+                    };
                 }
-            } else {
-                if args.len() == 0 {
-                    self.diag.report(ParserError::UnexpectedToken {
-                        found: peeked_token,
-                        while_doing: "trying to parse function arguments".to_string(),
-                    });
-                    return None;
+                Some(TokenKind::Ellipsis) if arg.is_var() => {
+                    let ellipsis = self.lexer.next_token()?;
+                    arg = Expr {
+                        span: arg.span.as_ref().map(|span| span.join(&ellipsis.span)),
+                        kind: ExprKind::Variadic(Box::new(arg)),
+                    };
                 }
-                return Some(Expr::Fun { name, params: args });
+                _ => (),
+            }
+            args.push(arg);
+            let token = self.expect(
+                &[TokenKind::CloseParen, TokenKind::Comma],
+                "while parsing functor".to_string(),
+            )?;
+            if token.kind == TokenKind::CloseParen {
+                break token;
+            }
+        };
+        self.depth -= 1;
+        let span = name_token.span.join(&close_paren.span);
+        if self.lexer.peek_kind() != Some(TokenKind::Equals) {
+            if let Some(variadic) = args.iter().find(|arg| arg.is_variadic()) {
+                self.error(ParserError::InvalidExpr {
+                    span: variadic.span.clone().unwrap_or(span),
+                    found: Box::new(variadic.clone()),
+                    reason: "only the last parameter of a function definition can take the remaining arguments".to_string(),
+                });
+                return None;
             }
         }
-        None
+        Some(Expr::new(ExprKind::Fun { name, params: args }, span))
     }
-    pub fn parse(&mut self, eval_env: &EvalEnv) -> Option<Expr> {
-        assert!(
-            self.stash.is_empty(),
-            "Expected stash to be empty when starting parsing, must be an implementation error"
-        );
-        if let Some(result) = self.parse_impl(eval_env, false) {
-            if !self.lexer.is_empty() {
-                if let None = self.lexer.expect_token_kinds(
-                    &[TokenKind::EOL],
-                    "while returning from parsing".to_string(),
-                ) {
-                    return None;
-                }
+    // parses the next statement, a blank statement or the end of the input is an error here,
+    // parse_program skips those
+    pub fn parse(&mut self, eval_env: &EvalEnv) -> Result<Expr, Vec<ParserError>> {
+        match self.parse_stmt(eval_env) {
+            Some(stmt) => Ok(stmt.into_expr()),
+            None => Err(mem::take(&mut self.errors)),
+        }
+    }
+    // parses statements until the end of the input, a statement with an error is skipped up to
+    // the next newline or ';' and parsing carries on after it, so every error in the input ends
+    // up in the program
+    pub fn parse_program(&mut self, eval_env: &EvalEnv) -> Program {
+        // names defined by earlier statements are visible to later ones before anything is
+        // evaluated, so parsing decisions like calls versus multiplication see them
+        let mut scope = eval_env.clone();
+        let mut program = Program::default();
+        while !self.at_end() {
+            let Some(stmt) = self.parse_stmt(&scope) else {
+                continue;
+            };
+            if let (Some(name), ExprKind::BinOp { right, .. }) =
+                (stmt.declared_name(), &stmt.expr().kind)
+            {
+                let name = name.to_string();
+                match stmt {
+                    Stmt::FuncDef(_) => scope.define(stmt.expr().clone()),
+                    _ => {
+                        Rc::make_mut(&mut scope.vars).insert(name, right.clone());
+                    }
+                };
+            }
+            program.stmts.push(stmt);
+        }
+        program.errors = mem::take(&mut self.errors);
+        program
+    }
+    // skips blank statements, then whether the input is done
+    pub fn at_end(&mut self) -> bool {
+        while self.lexer.peek_kind().is_some() && self.at_stmt_end() {
+            self.lexer.next_token();
+        }
+        self.lexer.peek_kind().is_none()
+    }
+    // whether the next token ends a (possibly blank) statement
+    fn at_stmt_end(&mut self) -> bool {
+        matches!(
+            self.lexer.peek_kind(),
+            None | Some(TokenKind::EOL | TokenKind::EOF | TokenKind::Semicolon)
+        )
+    }
+    // parses the next statement, None if it has an error, which is reported and recorded
+    pub fn parse_stmt(&mut self, eval_env: &EvalEnv) -> Option<Stmt> {
+        if let Some(result) = self.parse_expr(eval_env, 0) {
+            if !matches!(self.lexer.peek_kind(), None | Some(TokenKind::EOF))
+                && self
+                    .expect(
+                        &[TokenKind::EOL, TokenKind::Semicolon],
+                        "while returning from parsing".to_string(),
+                    )
+                    .is_none()
+            {
+                self.skip_stmt();
+                return None;
             }
             // if result is a function definition, check whether all parameters are used
-            if let Expr::BinOp {
+            if let ExprKind::BinOp {
                 op_kind,
                 left,
                 right,
-            } = result.clone()
+            } = result.kind.clone()
             {
                 if op_kind == OperatorKind::Equals {
-                    if let Expr::Fun { name, params } = *left.clone() {
-                        // check for recursive functor definition
-                        if right.get_fun_names().contains(&name) {
-                            self.diag.report(ParserError::RecusiveFuncDef {
-                                func_def: Box::new(result),
+                    if let ExprKind::Fun { name, params } = left.kind.clone() {
+                        // parameters are patterns the arguments of a call are matched against. the
+                        // last one may take the remaining arguments, and once one has a default
+                        // value the ones after it need one too
+                        let invalid_param = params.iter().enumerate().find_map(|(i, param)| {
+                            let after_default = params[..i]
+                                .iter()
+                                .any(|earlier| earlier.named_arg().is_some());
+                            let reason = if !param.is_param() {
+                                ""
+                            } else if param.is_variadic() && i + 1 != params.len() {
+                                ", where only the last parameter can take the remaining arguments"
+                            } else if after_default
+                                && param.named_arg().is_none()
+                                && !param.is_variadic()
+                            {
+                                ", where parameters after one with a default value need one too"
+                            } else {
+                                return None;
+                            };
+                            Some((param, reason))
+                        });
+                        if let Some((param, reason)) = invalid_param {
+                            self.error(ParserError::InvalidFuncParam {
+                                found: Box::new(param.clone()),
+                                while_doing: format!("parsing {}{}", result, reason),
                             });
                             return None;
                         }
-                        // check for unused parameters in functor definition
-                        let used_vars = right.get_var_names();
-                        let mut unused_params = vec![];
-                        for param in params.clone() {
-                            match param {
-                                Expr::Variable(name) => {
-                                    if !used_vars.contains(&name) {
-                                        unused_params.push(name)
-                                    }
-                                }
-                                _ => {
-                                    self.diag.report(ParserError::InvalidFuncParam {
-                                        found: Box::new(param),
-                                        while_doing: format!("parsing {}", result),
-                                    });
-                                    return None;
-                                }
-                            }
+                        // clauses this one adds to, rather than replaces
+                        let earlier_clauses: Vec<&Expr> = eval_env
+                            .clauses(&name, params.len())
+                            .into_iter()
+                            .flatten()
+                            .filter(|clause| !clause.same_patterns(&result))
+                            .collect();
+                        // a clause replacing an earlier one takes its place, so only a catch-all
+                        // before that place hides it
+                        let preceding = eval_env
+                            .clauses(&name, params.len())
+                            .into_iter()
+                            .flatten()
+                            .take_while(|clause| !clause.same_patterns(&result));
+                        if let Some(catch_all) =
+                            preceding.into_iter().find(|clause| clause.is_catch_all())
+                        {
+                            self.error(ParserError::UnreachableClause {
+                                clause: Box::new(result.clone()),
+                                catch_all: Box::new((*catch_all).clone()),
+                            });
+                            return None;
                         }
-                        if unused_params.len() > 0 {
-                            self.diag.report(ParserError::UnusedParams {
-                                functor: left,
-                                func_def: right,
-                                unused_params,
+                        // a recursive call needs a conditional around it or an earlier clause as
+                        // base case to ever stop, unless it passes the parameters on unchanged.
+                        // evaluation limits the depth of those that may stop
+                        if let Some(recursive_call) = right.get_unguarded_funs().into_iter().find(|fun| {
+                            matches!(&fun.kind, ExprKind::Fun { name: fun_name, params: args }
+                                if *fun_name == name && (earlier_clauses.is_empty() || *args == params))
+                        }) {
+                            self.error(ParserError::RecusiveFuncDef {
+                                recursive_call: Box::new(recursive_call.clone()),
+                                func_def: Box::new(result),
                             });
                             return None;
                         }
-                        let params: Vec<String> = params.iter().map(|x| x.to_string()).collect();
-                        for var in used_vars {
-                            if !params.contains(&var) {
-                                if let None = eval_env.vars.get(&var) {
-                                    self.diag.report(ParserError::IncompleteFuncDef {
+                        // check for unused parameters in functor definition, a parameter holding a
+                        // function can be used by calling it. a clause matching on the shape of
+                        // its arguments, or falling back when earlier ones don't match, may ignore
+                        // parts of them
+                        if result.is_catch_all() && earlier_clauses.is_empty() {
+                            let defaults = params.iter().filter_map(|param| param.named_arg());
+                            let used_vars = defaults
+                                .flat_map(|(_, default)| default.get_var_names())
+                                .chain(right.get_var_names())
+                                .chain(right.get_fun_names())
+                                .collect::<Vec<String>>();
+                            let unused_params: Vec<Expr> = params
+                                .iter()
+                                .filter(|param| {
+                                    !used_vars.iter().any(|var| param.param_name() == Some(var))
+                                })
+                                .cloned()
+                                .collect();
+                            if !unused_params.is_empty() {
+                                self.error(ParserError::UnusedParams {
+                                    functor: left,
+                                    func_def: right,
+                                    unused_params,
+                                });
+                                return None;
+                            }
+                        }
+                        // the body can refer to all parameters, a default value only to those
+                        // before it
+                        let defaults = params.iter().enumerate().filter_map(|(i, param)| {
+                            let earlier: Vec<&str> =
+                                params[..i].iter().filter_map(Expr::param_name).collect();
+                            Some((earlier, param.named_arg()?.1))
+                        });
+                        let in_body: Vec<&str> =
+                            params.iter().flat_map(Expr::bound_names).collect();
+                        for (in_scope, expr) in defaults.chain([(in_body, &*right)]) {
+                            for var in expr.get_vars() {
+                                let var_name = var.expect_name("get_vars returned a non-variable");
+                                if !in_scope.contains(&var_name.as_str())
+                                    && !eval_env.vars.contains_key(var_name)
+                                    && !eval_env.funcs.contains_key(var_name)
+                                    && constant_value(var_name).is_none()
+                                {
+                                    self.error(ParserError::IncompleteFuncDef {
+                                        undefined_var: Box::new(var.clone()),
                                         func_def: Box::new(result),
-                                        undefined_var: var,
                                     });
                                     return None;
                                 }
@@ -614,111 +1737,77 @@ impl Parser {
                     }
                 }
             }
-            return Some(result);
+            self.take_lex_errors();
+            return Some(Stmt::from_expr(result));
         }
+        self.skip_stmt();
         None
     }
-    fn parse_impl(&mut self, eval_env: &EvalEnv, parsing_args: bool) -> Option<Expr> {
-        self.depth += 1;
-        while let Some(peek_token) = self.lexer.peek_token() {
-            match peek_token.kind {
-                TokenKind::EOL => {
-                    break;
-                }
-                TokenKind::OpenParen => {
-                    if let Some(stashed_expr) = self.stash.pop() {
-                        {
-                            println!("matched stashed expr {}", stashed_expr);
-                            match stashed_expr {
-                                Expr::Variable(name) => {
-                                    self.depth -= 1;
-                                    return self.parse_functor(name, eval_env);
-                                }
+    // drops the rest of the statement after a syntax error, so the next parse starts on a fresh
+    // statement and any lexical errors further along still get reported
+    fn skip_stmt(&mut self) {
+        self.depth = 0;
+        self.params.clear();
+        while let Some(token) = self.lexer.next_token() {
+            if matches!(token.kind, TokenKind::EOL | TokenKind::Semicolon) {
+                break;
+            }
+        }
+        self.take_lex_errors();
+    }
+}
 
-                                _ => {
-                                    self.diag.report(ParserError::UnexpectedToken {
-                                        found: peek_token,
-                                        while_doing: format!(
-                                            "parsing expression after {}",
-                                            stashed_expr
-                                        ),
-                                    });
-                                    return None;
-                                }
-                            }
-                        }
-                    }
-                    self.lexer.drop_token();
-                    let result = self.parse_impl(eval_env, false)?;
-                    let _ = self.lexer.expect_token_kinds(
-                        &[TokenKind::CloseParen],
-                        "while parsing expression between parens".to_string(),
-                    )?;
-                    self.stash.push(Expr::Group(Box::new(result)))
-                }
-                TokenKind::CloseParen => {
-                    break;
-                }
-                TokenKind::Comma => {
-                    if parsing_args {
-                        break;
-                    } else {
-                        self.diag.report(ParserError::UnexpectedToken {
-                            found: peek_token,
-                            while_doing: "not parsing args".to_string(),
-                        });
-                        return None;
-                    }
-                }
-                TokenKind::Ident | TokenKind::NumLit | TokenKind::Bool => {
-                    if let Some(expr) = self.stash.last() {
-                        let while_doing = format!("Parsing after expression {}", expr);
-                        self.diag.report(ParserError::UnexpectedToken {
-                            found: peek_token,
-                            while_doing,
-                        });
-                        return None;
-                    }
-                    let expr = self.parse_operand(eval_env)?;
-                    self.stash.push(expr);
-                }
-                x if x.is_operator() => {
-                    if x == TokenKind::Equals && self.depth != 1 {
-                        let while_doing = "while not parsing a top-level operator.\
-                         Equals is only allowed as the main expression, not in a subexpression"
-                            .to_string();
+// span from the start of first to the end of last, if both were parsed from source
+fn span_between(first: &Expr, last: &Expr) -> Option<Span> {
+    Some(first.span.as_ref()?.join(last.span.as_ref()?))
+}
 
-                        self.diag.report(ParserError::UnexpectedToken {
-                            found: peek_token,
-                            while_doing,
-                        });
-                        return None;
-                    }
-                    let left = self.stash.pop()?;
-                    let expr = self.parse_binop(left, eval_env)?;
-                    self.stash.push(expr)
-                }
-                TokenKind::Equals
-                | TokenKind::Mult
-                | TokenKind::Div
-                | TokenKind::Plus
-                | TokenKind::Min
-                | TokenKind::Pow
-                | TokenKind::DoubleEquals => {
-                    let msg = match parsing_args {
-                        true => "parsing function arguments",
-                        false => "parsing",
-                    }
-                    .to_string();
-                    self.diag.report(ParserError::UnexpectedToken {
-                        found: peek_token,
-                        while_doing: msg,
-                    });
-                    return None;
-                }
-            }
+fn superscript_digit(x: char) -> Option<f64> {
+    let digit = match x {
+        '⁰' => 0,
+        '¹' => 1,
+        '²' => 2,
+        '³' => 3,
+        '⁴' => 4,
+        '⁵' => 5,
+        '⁶' => 6,
+        '⁷' => 7,
+        '⁸' => 8,
+        '⁹' => 9,
+        _ => return None,
+    };
+    Some(digit as f64)
+}
+fn is_whitespace(x: char) -> bool {
+    x == ' ' || x == '\t' || x == '\r'
+}
+// text of the line starting at chars[0], without its line ending
+fn line_text(chars: &[char]) -> String {
+    let line: String = chars.iter().take_while(|&&x| x != '\n').collect();
+    line.trim_end_matches('\r').to_string()
+}
+// reconstructs the exact source text of tokens lexed in lossless mode
+pub fn source_text(tokens: &[Token]) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        for trivia in &token.trivia {
+            out.push_str(&trivia.text);
         }
-        self.depth -= 1;
-        self.stash.pop()
+        out.push_str(&token.value);
     }
+    out
+}
+// value of a numeric literal as produced by Lexer::lex_num_lit
+fn num_lit_value(literal: &str) -> Option<f64> {
+    let digits = literal.replace('_', "");
+    let radix = match digits.get(..2) {
+        Some("0x" | "0X") => 16,
+        Some("0b" | "0B") => 2,
+        Some("0o" | "0O") => 8,
+        // a decimal literal beyond the range of f64 parses as infinity
+        _ => return digits.parse::<f64>().ok().filter(|value| value.is_finite()),
+    };
+    u64::from_str_radix(&digits[2..], radix)
+        .ok()
+        .map(|value| value as f64)
 }
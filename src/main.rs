@@ -1,4 +1,4 @@
-use std::io;
+use std::{io, panic, thread};
 
 #[allow(dead_code)]
 mod diag;
@@ -11,13 +11,27 @@ pub mod lexer;
 #[allow(dead_code)]
 #[allow(unused_mut)]
 mod repl;
+use expr::{MAX_DEPTH, STACK_PER_CALL};
 use repl::Repl;
 mod test;
 
 #[allow(dead_code)]
 #[allow(unused_mut)]
 fn main() -> io::Result<()> {
+    // deep recursion needs more stack than the main thread has
+    let session = thread::Builder::new()
+        .stack_size(MAX_DEPTH * STACK_PER_CALL)
+        .spawn(run)?;
+    session
+        .join()
+        .unwrap_or_else(|err| panic::resume_unwind(err))
+}
+fn run() -> io::Result<()> {
     let mut repl = Repl::new();
-    repl.run()?;
+    // a file given as argument is run as a program, otherwise start an interactive session
+    match std::env::args().nth(1) {
+        Some(path) => repl.run_file(&path)?,
+        None => repl.run()?,
+    }
     Ok(())
 }
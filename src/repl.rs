@@ -1,6 +1,8 @@
-use crate::expr::{EvalEnv, Expr};
+use crate::diag::Diagnoster;
+use crate::expr::{EvalEnv, ExprKind, Program, Stmt, MAX_DEPTH};
 use crate::lexer::Parser;
-use std::io::{self, Stdout};
+use std::fs::File;
+use std::io::{self, BufReader, Stdout};
 use std::io::{Stdin, Write};
 use std::vec::Vec;
 pub struct Repl {
@@ -24,6 +26,16 @@ impl Repl {
             quit: false,
             eval_env: EvalEnv::new(),
         }
+        .with_max_depth(MAX_DEPTH)
+    }
+    // the repl runs on a thread with stack for max_depth nested calls
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.eval_env.max_depth = max_depth;
+        self
+    }
+    pub fn with_diag(mut self, diag: Diagnoster) -> Self {
+        self.eval_env.diag = diag;
+        self
     }
     fn read_input(&mut self) -> io::Result<()> {
         self.input.clear();
@@ -49,43 +61,84 @@ impl Repl {
             println!("---------------------------------------");
             println!("Functors");
             println!("---------------------------------------");
-            for (_, body) in self.eval_env.funcs.iter() {
-                println!("{}", body);
+            for clause in self
+                .eval_env
+                .funcs
+                .values()
+                .flat_map(|arities| arities.values().flatten())
+            {
+                println!("{}", clause);
             }
         }
         if no_vars && no_funcs {
             println!("No variables or functors in local environment!");
         }
     }
+    // evaluates the statements of program that did parse and prints their values,
+    // parse errors have already been reported
+    fn run_program(&mut self, program: Program) {
+        for stmt in &program.stmts {
+            self.run_stmt(stmt);
+        }
+    }
+    // evaluates stmt and prints its value
+    fn run_stmt(&mut self, stmt: &Stmt) {
+        let Some(val) = stmt.eval(&mut self.eval_env) else {
+            return;
+        };
+        let prefix = match val.kind {
+            ExprKind::Numeric(_) => "Num",
+            ExprKind::Bool(_) => "Bool",
+            ExprKind::List(_) => "List",
+            ExprKind::Tuple(_) => "Tuple",
+            ExprKind::Matrix(_) => "Matrix",
+            _ => "Sym",
+        };
+        if self.debug_mode {
+            println!("  => {prefix}: {val:?}");
+        } else {
+            println!("  => {prefix}: {val}");
+        }
+    }
+    pub fn run_file(&mut self, path: &str) -> io::Result<()> {
+        let file = File::open(path)?;
+        let mut parser =
+            Parser::from_reader(BufReader::new(file), path).with_diag(self.eval_env.diag.clone());
+        // each statement is evaluated before the next is read, so a long script is never held
+        // in memory as a whole
+        while !parser.at_end() {
+            if let Some(stmt) = parser.parse_stmt(&self.eval_env) {
+                self.run_stmt(&stmt);
+            }
+        }
+        Ok(())
+    }
     pub fn run(&mut self) -> io::Result<()> {
         while !self.quit {
             self.read_input()?;
-            match self.input.as_str() {
-                command if command.ends_with(';') => match command.strip_suffix(';').unwrap() {
-                    "quit" | "q" => break,
-                    "debug" | "db" => {
-                        self.debug_mode = !self.debug_mode;
-                        println!("Debug mode set to {}", self.debug_mode);
-                    }
-                    "locals" | "ls" => self.print_locals(),
-                    otherwise => println!("Unknown command {}", otherwise),
-                },
-                input => {
-                    let mut parser = Parser::from_string(input.to_string());
-                    let mut prefix;
-                    if let Some(expr) = parser.parse(&self.eval_env) {
-                        let val = expr.eval(&mut self.eval_env);
-                        prefix = match val {
-                            Expr::Numeric(_) => "Num",
-                            Expr::Bool(__) => "Bool",
-                            _ => "Sym",
-                        };
-                        if self.debug_mode {
-                            println!("  => {prefix}: {val:?}");
-                        } else {
-                            println!("  => {prefix}: {val}");
+            // commands end in ';', anything else is a program whose statements can also be
+            // separated by ';'
+            match self.input.strip_suffix(';') {
+                Some("quit" | "q") => break,
+                Some("debug" | "db") => {
+                    self.debug_mode = !self.debug_mode;
+                    println!("Debug mode set to {}", self.debug_mode);
+                }
+                Some("locals" | "ls") => self.print_locals(),
+                Some(command) if command.starts_with("depth ") => {
+                    match command["depth ".len()..].trim().parse() {
+                        Ok(max_depth) if max_depth <= MAX_DEPTH => {
+                            self.eval_env.max_depth = max_depth;
+                            println!("Recursion limit set to {}", max_depth);
                         }
-                    };
+                        Ok(_) => println!("The recursion limit can be at most {}", MAX_DEPTH),
+                        Err(_) => println!("Expected a number of calls after depth"),
+                    }
+                }
+                _ => {
+                    let mut parser = Parser::new(&self.input).with_diag(self.eval_env.diag.clone());
+                    let program = parser.parse_program(&self.eval_env);
+                    self.run_program(program);
                 }
             }
         }
@@ -1,9 +1,17 @@
 #[cfg(test)]
 mod tests {
 
+    use std::io::{BufReader, Read};
+    use std::rc::Rc;
+    use std::thread;
+
     use crate::{
-        expr::{EvalEnv, Expr},
-        lexer::{Lexer, Parser, TokenKind},
+        diag::{
+            format_problem_area, CollectSink, Diagnoster, LexError, LogLevel, ParserError, Problem,
+        },
+        expr::{EvalEnv, Expr, ExprKind, Stmt, DEFAULT_MAX_DEPTH, MAX_DEPTH, STACK_PER_CALL},
+        lexer::{source_text, Lexer, Loc, Parser, Token, TokenKind, TriviaKind},
+        repl::Repl,
     };
 
     fn start_test(name: &str) {
@@ -16,6 +24,42 @@ mod tests {
         println!("end {} test", name);
         println!("--------------------------------------------");
     }
+    // fully parenthesized form of expr, so the shape of the tree is visible
+    fn sexpr(expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::BinOp {
+                op_kind,
+                left,
+                right,
+            } => format!("({} {} {})", op_kind, sexpr(left), sexpr(right)),
+            ExprKind::UnaryOp { op_kind, operand } => format!("({} {})", op_kind, sexpr(operand)),
+            ExprKind::Fun { name, params } => {
+                let params: Vec<String> = params.iter().map(sexpr).collect();
+                format!("{}({})", name, params.join(","))
+            }
+            ExprKind::Group(inner) => sexpr(inner),
+            _ => expr.to_string(),
+        }
+    }
+    fn test_shape(input: &str, expected: &str) {
+        let mut parser = Parser::from_string(input.to_string());
+        let expr = parser
+            .parse(&EvalEnv::new())
+            .expect("failed to parse expression");
+        assert_eq!(sexpr(&expr), expected, "wrong shape for {}", input);
+    }
+    // parses and evaluates input, None if either fails. parse errors are reported to the
+    // diagnoster of eval_env like evaluation errors
+    fn eval_on_string(input: &str, eval_env: &mut EvalEnv) -> Option<Expr> {
+        let mut parser = Parser::from_string(input.to_string()).with_diag(eval_env.diag.clone());
+        parser.parse(eval_env).ok()?.eval(eval_env)
+    }
+    // empty environment collecting its diagnostics, so tests can check what was reported
+    fn collecting_env() -> (Rc<CollectSink>, EvalEnv) {
+        let sink = Rc::new(CollectSink::default());
+        let eval_env = EvalEnv::with_diag(Diagnoster::new(sink.clone()));
+        (sink, eval_env)
+    }
     #[test]
     fn test_expect_token_kinds() {
         start_test("expect_token_kinds");
@@ -25,13 +69,13 @@ mod tests {
         fn assert_token_kind(lexer: &mut Lexer, kind: TokenKind) {
             assert_eq!(
                 (&lexer
-                    .expect_token_kinds(&[kind.clone()], "while testing".to_string())
+                    .expect_token_kinds(&[kind], "while testing".to_string())
                     .unwrap()
                     .kind),
                 (&kind),
                 "Expected{:?} but got {:?}",
                 lexer
-                    .expect_token_kinds(&[kind.clone()], "while testing".to_string())
+                    .expect_token_kinds(&[kind], "while testing".to_string())
                     .unwrap()
                     .kind,
                 kind
@@ -69,7 +113,7 @@ mod tests {
             let mut parser = Parser::from_string(input.to_string());
             let eval_env = EvalEnv::new();
             let expr = match parser.parse(&eval_env) {
-                Some(expr) => {
+                Ok(expr) => {
                     assert!(
                         !should_fail,
                         "Parsed {} from {}, expected to fail.",
@@ -77,7 +121,7 @@ mod tests {
                     );
                     expr
                 }
-                None => return assert!(should_fail, "Failed on {}, but didn't expect to", input),
+                Err(_) => return assert!(should_fail, "Failed on {}, but didn't expect to", input),
             };
             println!("{}", expr);
             println!("{:#?}", expr);
@@ -99,7 +143,7 @@ mod tests {
         test_parser_on_string("1+2)*3", true);
         test_parser_on_string("ab 123", true);
         test_parser_on_string("f(a,b", true);
-        test_parser_on_string("f(a b)", true);
+        test_parser_on_string("f(a 1)", true);
 
         end_test("parser");
     }
@@ -115,6 +159,7 @@ mod tests {
             let mut eval_env = EvalEnv::new();
             let val = expr
                 .eval(&mut eval_env)
+                .expect("failed to evaluate expression")
                 .expect_val("could not evaluate expr");
             println!("{} evaluated to {}", expr, val);
 
@@ -143,16 +188,126 @@ mod tests {
         end_test("expr_eval");
     }
     #[test]
+    fn test_num_lits() {
+        start_test("numeric literals");
+        fn test_num_lit_on_string(input: &str, expected: Option<f64>) {
+            let mut parser = Parser::from_string(input.to_string());
+            let eval_env = EvalEnv::new();
+            match (parser.parse(&eval_env), expected) {
+                (Ok(expr), Some(expected)) => {
+                    let val = expr
+                        .eval(&mut EvalEnv::new())
+                        .expect("failed to evaluate expression");
+                    assert_eq!(
+                        val.expect_val("could not evaluate expr"),
+                        expected,
+                        "evaluating {} did not yield {}",
+                        input,
+                        expected
+                    );
+                }
+                (Err(_), None) => (),
+                (Ok(expr), None) => panic!("Parsed {} from {}, expected to fail.", expr, input),
+                (Err(_), Some(_)) => panic!("Failed on {}, but didn't expect to", input),
+            }
+        }
+        test_num_lit_on_string("6.022e23", Some(6.022e23));
+        test_num_lit_on_string("1e-9", Some(1e-9));
+        test_num_lit_on_string("2.5E+3", Some(2500.0));
+        test_num_lit_on_string("1_000_000", Some(1_000_000.0));
+        test_num_lit_on_string("1_000.000_5", Some(1_000.000_5));
+        test_num_lit_on_string("0x1F", Some(31.0));
+        test_num_lit_on_string("0xff_ff", Some(65535.0));
+        test_num_lit_on_string("0b1010", Some(10.0));
+        test_num_lit_on_string("0o17", Some(15.0));
+        test_num_lit_on_string("0x10+0b10*1e1", Some(36.0));
+        test_num_lit_on_string("1.", Some(1.0));
+        test_num_lit_on_string("1e-", None);
+        test_num_lit_on_string("1e+", None);
+        test_num_lit_on_string("1.2.3", None);
+        test_num_lit_on_string("1__0", None);
+        test_num_lit_on_string("1_", None);
+        test_num_lit_on_string("1_.5", None);
+        test_num_lit_on_string("0x", None);
+        test_num_lit_on_string("0x1G", None);
+        test_num_lit_on_string("0b102", None);
+        test_num_lit_on_string("0x1.5", None);
+        test_num_lit_on_string("0x1_0000_0000_0000_0000", None);
+        test_num_lit_on_string("1e999", None);
+        test_num_lit_on_string("1e308", Some(1e308));
+        end_test("numeric literals");
+    }
+    #[test]
+    fn test_unicode_math() {
+        start_test("unicode math");
+        let mut lexer = Lexer::from_string("θ₁² × Δt·π − √x ÷ 2".to_string());
+        let mut tokens = vec![];
+        while let Some(token) = lexer.next_token() {
+            tokens.push((token.kind, token.to_string()));
+        }
+        assert_eq!(
+            tokens,
+            [
+                (TokenKind::Ident, "θ₁".to_string()),
+                (TokenKind::Superscript, "superscript".to_string()),
+                (TokenKind::Mult, "*".to_string()),
+                (TokenKind::Ident, "Δt".to_string()),
+                (TokenKind::Mult, "*".to_string()),
+                (TokenKind::Ident, "π".to_string()),
+                (TokenKind::Min, "-".to_string()),
+                (TokenKind::Sqrt, "√".to_string()),
+                (TokenKind::Ident, "x".to_string()),
+                (TokenKind::Div, "/".to_string()),
+                (TokenKind::NumLit, "2".to_string()),
+            ]
+        );
+
+        fn test_unicode_eval_on_string(input: &str, eval_env: &mut EvalEnv, expected: f64) {
+            let mut parser = Parser::from_string(input.to_string());
+            let expr = parser.parse(eval_env).expect("failed to parse expression");
+            let val = expr
+                .eval(eval_env)
+                .expect("failed to evaluate expression")
+                .expect_val("could not evaluate expr");
+            assert_eq!(
+                val, expected,
+                "evaluating {} did not yield {}",
+                input, expected
+            );
+        }
+        let mut eval_env = EvalEnv::new();
+        test_unicode_eval_on_string("3×4", &mut eval_env, 12.0);
+        test_unicode_eval_on_string("2·3", &mut eval_env, 6.0);
+        test_unicode_eval_on_string("8÷2", &mut eval_env, 4.0);
+        test_unicode_eval_on_string("5−7", &mut eval_env, -2.0);
+        test_unicode_eval_on_string("√16", &mut eval_env, 4.0);
+        test_unicode_eval_on_string("√(9+16)×2", &mut eval_env, 10.0);
+        test_unicode_eval_on_string("3²", &mut eval_env, 9.0);
+        test_unicode_eval_on_string("1+2³×2", &mut eval_env, 17.0);
+        test_unicode_eval_on_string("2¹⁰", &mut eval_env, 1024.0);
+        test_unicode_eval_on_string("2⁻¹", &mut eval_env, 0.5);
+        test_unicode_eval_on_string("π", &mut eval_env, std::f64::consts::PI);
+        for definition in ["θ₁=3", "Δt=0.5", "area(r)=π×r²"] {
+            let mut parser = Parser::from_string(definition.to_string());
+            let expr = parser.parse(&eval_env).expect("failed to parse expression");
+            expr.eval(&mut eval_env);
+        }
+        test_unicode_eval_on_string("θ₁²", &mut eval_env, 9.0);
+        test_unicode_eval_on_string("θ₁÷Δt", &mut eval_env, 6.0);
+        test_unicode_eval_on_string("area(2)", &mut eval_env, std::f64::consts::PI * 4.0);
+        end_test("unicode math");
+    }
+    #[test]
     fn test_functor_parsing() {
         start_test("functor parsing");
         fn test_functor_parsing_on_str(input: &str, eval_env: &mut EvalEnv, should_fail: bool) {
             let mut parser = Parser::from_string(input.to_string());
-            let expr = parser.parse(&eval_env);
+            let expr = parser.parse(eval_env);
             if should_fail {
-                return assert!(expr.is_none(), "expected to fail while parsing {}", input);
+                assert!(expr.is_err(), "expected to fail while parsing {}", input);
             } else {
                 let expr = expr.expect("failed to parse expression");
-                let val = expr.eval(eval_env);
+                let val = expr.eval(eval_env).expect("failed to evaluate expression");
                 println!("input: {} parsed to: {}, evaluated to:{}", input, expr, val);
             }
         }
@@ -161,11 +316,11 @@ mod tests {
         test_functor_parsing_on_str("g(a,b,c)", &mut eval_env, false);
         test_functor_parsing_on_str("f(1,a,3)", &mut eval_env, false);
         test_functor_parsing_on_str("f(1,g(2))", &mut eval_env, false);
-        test_functor_parsing_on_str("f(1,a)=a", &mut eval_env, true);
-        test_functor_parsing_on_str("f(a,b)=a", &mut eval_env, true);
+        test_functor_parsing_on_str("f(1,a)=a", &mut eval_env, false);
+        test_functor_parsing_on_str("g(a,b)=a", &mut eval_env, true);
         test_functor_parsing_on_str("f(a,b)=f(a,b)", &mut eval_env, true);
         test_functor_parsing_on_str("f(a)=a", &mut eval_env, false);
-        test_functor_parsing_on_str("f(1,2)", &mut eval_env, true);
+        test_functor_parsing_on_str("f(1,2)", &mut eval_env, false);
         test_functor_parsing_on_str("f(a,b)=(b+(a-(f(1,2))", &mut eval_env, true);
         test_functor_parsing_on_str("f(a,b)=a+b+c", &mut eval_env, true);
 
@@ -181,7 +336,7 @@ mod tests {
             let mut parser = Parser::from_string(input.to_string());
             let expr = parser.parse(eval_env).expect("failed to parse expression");
             println!("{}", expr);
-            let val = expr.eval(eval_env);
+            let val = expr.eval(eval_env).expect("failed to evaluate expression");
             println!("{} evaluated to {}", expr, val);
             if let Some(expected) = expected {
                 assert_eq!(
@@ -211,7 +366,7 @@ mod tests {
         fn test_fun_eval_on_string(input: &str, eval_env: &mut EvalEnv, expected: Option<f64>) {
             let mut parser = Parser::from_string(input.to_string());
             let expr = parser.parse(eval_env).expect("failed to parse expression");
-            let val = expr.eval(eval_env);
+            let val = expr.eval(eval_env).expect("failed to evaluate expression");
             println!("{} evaluated to {}", expr, val);
             if let Some(expected) = expected {
                 assert_eq!(
@@ -238,10 +393,12 @@ mod tests {
         fn test_file_eval(input_path: &str, eval_env: &mut EvalEnv, expected: Option<f64>) {
             let mut parser =
                 Parser::from_file(input_path).expect("failed file read while testing file parsing");
-            let mut val = Expr::Variable("default".to_string());
-            while let Some(expr) = parser.parse(&eval_env) {
-                val = expr.eval(eval_env);
-                println!("{} evaluated to {}", expr, val);
+            let program = parser.parse_program(eval_env);
+            assert!(program.is_ok(), "failed to parse {}", input_path);
+            let mut val = Expr::from(ExprKind::Variable("default".to_string()));
+            for stmt in &program.stmts {
+                val = stmt.eval(eval_env).expect("failed to evaluate expression");
+                println!("{} evaluated to {}", stmt, val);
             }
             if let Some(expected) = expected {
                 assert_eq!(
@@ -260,18 +417,821 @@ mod tests {
             &mut eval_env,
             Some(81.0),
         );
+        let mut eval_env = EvalEnv::new();
+        test_file_eval(
+            "./src/test/file_parsing/test2.txt",
+            &mut eval_env,
+            Some(10.0),
+        );
+
+        // a script is evaluated one statement at a time, so a statement fails before the ones
+        // after it are read
+        let sink = Rc::new(CollectSink::default());
+        let mut repl = Repl::new().with_diag(Diagnoster::new(sink.clone()));
+        repl.run_file("./src/test/file_parsing/errors.txt")
+            .expect("failed file read while testing file parsing");
+        let messages: Vec<String> = sink
+            .take()
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "Operator + expects numeric operands, found true.",
+                "Found unexpected '$'."
+            ]
+        );
 
         // test_file_eval("", &mut env,None);
         end_test("file parsing/evaluation");
     }
     #[test]
+    fn test_program() {
+        start_test("program");
+        let mut eval_env = EvalEnv::new();
+        let mut parser = Parser::from_string("a = 2; f(x) = x + a\n\n;\nf(a) * 3".to_string());
+        let program = parser.parse_program(&eval_env);
+        assert!(program.is_ok());
+        let kinds: Vec<&str> = program
+            .stmts
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::Assign(_) => "assign",
+                Stmt::FuncDef(_) => "funcdef",
+                Stmt::Expr(_) => "expr",
+            })
+            .collect();
+        assert_eq!(kinds, ["assign", "funcdef", "expr"]);
+        let vals = program.eval(&mut eval_env);
+        assert_eq!(vals[2].as_ref().map(|val| val.expect_val("")), Some(12.0));
+
+        // a name assigned earlier in the program is a value, so a(1) is a multiplication
+        let mut parser = Parser::from_string("b = 3; b(1)".to_string());
+        let program = parser.parse_program(&EvalEnv::new());
+        assert_eq!(program.stmts[1].expr().to_string(), "b*(1)");
+
+        // a name holding anything else may hold a function, and a call multiplies if it does not
+        let input = "sq(x) = x^2; g = sq; g(3); q = compose(sq, sq); q(2); c = 2 + 3; c(4)";
+        let mut eval_env = EvalEnv::new();
+        let program = Parser::from_string(input.to_string()).parse_program(&eval_env);
+        let vals: Vec<String> = program
+            .eval(&mut eval_env)
+            .iter()
+            .map(|val| val.as_ref().map_or("failed".to_string(), Expr::to_string))
+            .collect();
+        assert_eq!([&vals[2], &vals[4], &vals[6]], ["9", "16", "20"]);
+
+        // errors are reported per statement and parsing carries on after them
+        let mut parser = Parser::from_string("1 +; 2 )\n3; (4".to_string());
+        let program = parser.parse_program(&EvalEnv::new());
+        assert_eq!(program.errors.len(), 3);
+        let stmts: Vec<String> = program.stmts.iter().map(Stmt::to_string).collect();
+        assert_eq!(stmts, ["3"]);
+
+        // an empty input is an empty program rather than an error
+        let program = Parser::from_string("\n;\n".to_string()).parse_program(&EvalEnv::new());
+        assert!(program.is_ok() && program.stmts.is_empty());
+        end_test("program");
+    }
+    #[test]
+    fn test_parser_error_recovery() {
+        start_test("parser error recovery");
+        let input = "a = 1\nb = (2 +\nc = 3 $ 4; d = a +* 2\n1 2 )\ne = a + c";
+        let mut parser = Parser::from_string(input.to_string());
+        let program = parser.parse_program(&EvalEnv::new());
+        let stmts: Vec<String> = program.stmts.iter().map(Stmt::to_string).collect();
+        assert_eq!(stmts, ["a=1", "e=a+c"]);
+        let errors: Vec<String> = program.errors.iter().map(ParserError::to_string).collect();
+        for error in &errors {
+            println!("{}", error);
+        }
+        assert_eq!(errors.len(), 4);
+        assert!(matches!(
+            program.errors[0],
+            ParserError::ExpectedToken { .. }
+        ));
+        assert!(matches!(
+            program.errors[1],
+            ParserError::Lex(LexError::UnexpectedChar { char: '$', .. })
+        ));
+        assert!(matches!(
+            program.errors[2],
+            ParserError::ExpectedToken { .. }
+        ));
+        assert!(matches!(
+            program.errors[3],
+            ParserError::UnexpectedToken { .. }
+        ));
+        end_test("parser error recovery");
+    }
+    #[test]
+    fn test_diag_sink() {
+        start_test("diagnostic sink");
+        let sink = Rc::new(CollectSink::default());
+        let diag = Diagnoster::new(sink.clone());
+        let mut eval_env = EvalEnv::with_diag(diag.clone());
+
+        // parse errors are returned and reported to the sink
+        let mut parser = Parser::from_string("1 + $\n(2".to_string()).with_diag(diag.clone());
+        let errors = parser.parse(&eval_env).expect_err("parsed invalid input");
+        assert!(matches!(
+            errors[..],
+            [ParserError::Lex(LexError::UnexpectedChar { char: '$', .. })]
+        ));
+        let errors = parser.parse(&eval_env).expect_err("parsed invalid input");
+        assert!(matches!(errors[..], [ParserError::ExpectedToken { .. }]));
+
+        // evaluation reports to the same sink, also from inside function calls
+        Parser::from_string("f(x) = x + 1".to_string())
+            .with_diag(diag.clone())
+            .parse(&eval_env)
+            .expect("failed to parse expression")
+            .eval(&mut eval_env);
+        let expr = Parser::from_string("f(true)".to_string())
+            .with_diag(diag.clone())
+            .parse(&eval_env)
+            .expect("failed to parse expression");
+        assert!(expr.eval(&mut eval_env).is_none());
+
+        // and so does a lexer driven on its own
+        let mut lexer = Lexer::from_string("@".to_string()).with_diag(diag);
+        assert_eq!(
+            lexer.next_token().map(|token| token.kind),
+            Some(TokenKind::Error)
+        );
+
+        let diagnostics = sink.take();
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages.len(), 4, "{:?}", messages);
+        assert_eq!(messages[0], "Found unexpected '$'.");
+        assert!(messages[2].starts_with("Operator + expects"));
+        assert_eq!(messages[3], "Found unexpected '@'.");
+        assert!(diagnostics.iter().all(|d| d.level == LogLevel::Error));
+        assert!(sink.take().is_empty());
+        end_test("diagnostic sink");
+    }
+    #[test]
+    fn test_file_locs() {
+        start_test("file locations");
+        let mut lexer = Lexer::from_file("./src/test/file_parsing/test1.txt")
+            .expect("failed file read while testing file locations");
+        let mut tokens = vec![];
+        while let Some(token) = lexer.next_token() {
+            tokens.push(token);
+        }
+        let positions: Vec<(String, usize, usize)> = tokens
+            .iter()
+            .map(|token| match &token.span.start {
+                Loc::File { ln, col, .. } => (token.to_string(), *ln, *col),
+                Loc::Repl { .. } => panic!("expected file location for {}", token),
+            })
+            .collect();
+        assert_eq!(positions[0], ("g".to_string(), 1, 1));
+        assert_eq!(positions[6], ("=".to_string(), 1, 7));
+        assert_eq!(positions[7], ("11".to_string(), 1, 8));
+        assert_eq!(positions[14], ("\\n".to_string(), 1, 17));
+        assert_eq!(positions[15], ("a".to_string(), 2, 1));
+        assert_eq!(positions[17], ("3".to_string(), 2, 3));
+        assert_eq!(positions.last().unwrap(), &(")".to_string(), 4, 6));
+
+        let area = format_problem_area(&tokens[17].span);
+        assert_eq!(
+            area,
+            "  --> ./src/test/file_parsing/test1.txt:2:3\n    a=3\n      ^\n"
+        );
+
+        // string inputs keep track of their lines too, whether borrowed or streamed
+        let input = "a = 1\r\nb = 2 +\n  c";
+        for lexer in [Lexer::new(input), Lexer::from_string(input.to_string())] {
+            let token = lexer
+                .map(|token| token.unwrap())
+                .find(|token| token.value() == "c")
+                .unwrap();
+            assert!(matches!(token.span.start, Loc::Repl { ln: 3, idx: 2, .. }));
+            assert_eq!(token.span.start.to_string(), "repl:3:3");
+            assert_eq!(format_problem_area(&token.span), "      c\n      ^\n");
+        }
+        end_test("file locations");
+    }
+    #[test]
+    fn test_streaming_lexer() {
+        start_test("streaming lexer");
+        // produces "x = 0\nx + 1\nx + 2\n..." without ever holding the whole input
+        struct Formulas {
+            line: usize,
+            lines: usize,
+            pending: Vec<u8>,
+        }
+        impl Read for Formulas {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.pending.is_empty() && self.line < self.lines {
+                    self.pending = match self.line {
+                        0 => "x = 0\n".to_string(),
+                        n => format!("x + {}\n", n),
+                    }
+                    .into_bytes();
+                    self.line += 1;
+                }
+                let len = self.pending.len().min(buf.len());
+                buf[..len].copy_from_slice(&self.pending[..len]);
+                self.pending.drain(..len);
+                Ok(len)
+            }
+        }
+        let lines = 2000;
+        let formulas = Formulas {
+            line: 0,
+            lines,
+            pending: vec![],
+        };
+        let mut parser = Parser::from_reader(BufReader::with_capacity(4, formulas), "formulas");
+        let mut eval_env = EvalEnv::new();
+        for evaluated in 0..lines {
+            let val = parser
+                .parse(&eval_env)
+                .expect("failed to parse expression")
+                .eval(&mut eval_env)
+                .expect("failed to evaluate expression");
+            if evaluated > 0 {
+                assert_eq!(val.expect_val("could not evaluate expr"), evaluated as f64);
+            }
+        }
+        assert!(parser.parse(&eval_env).is_err());
+
+        // locations stay correct across reads that split lines and tokens
+        let input = "abc + 12\n  f(x) /* a\nb */ 0x1F\n";
+        let mut lexer = Lexer::from_reader(BufReader::with_capacity(2, input.as_bytes()), "chunks");
+        let mut positions = vec![];
+        while let Some(token) = lexer.next_token() {
+            if let Loc::File { ln, col, line, .. } = &token.span.start {
+                positions.push((token.to_string(), *ln, *col, line.to_string()));
+            }
+        }
+        let expected = [
+            ("abc", 1, 1, "abc + 12"),
+            ("+", 1, 5, "abc + 12"),
+            ("12", 1, 7, "abc + 12"),
+            ("\\n", 1, 9, "abc + 12"),
+            ("f", 2, 3, "  f(x) /* a"),
+            ("(", 2, 4, "  f(x) /* a"),
+            ("x", 2, 5, "  f(x) /* a"),
+            (")", 2, 6, "  f(x) /* a"),
+            ("0x1F", 3, 6, "b */ 0x1F"),
+            ("\\n", 3, 10, "b */ 0x1F"),
+        ];
+        let expected: Vec<(String, usize, usize, String)> = expected
+            .iter()
+            .map(|(token, ln, col, line)| (token.to_string(), *ln, *col, line.to_string()))
+            .collect();
+        assert_eq!(positions, expected);
+
+        // invalid utf-8 is reported and ends the input, with an Error token where it stops
+        let mut lexer = Lexer::from_reader(&b"a\n\xff + b\n"[..], "binary");
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Ident);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::EOL);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Error);
+        assert!(lexer.next_token().is_none());
+
+        // each error comes with the token it belongs to, even when reading fails while a
+        // comment is still open
+        let results: Vec<_> = Lexer::from_reader(&b"1 /* open\n\xff\n"[..], "binary").collect();
+        assert!(
+            matches!(
+                results.as_slice(),
+                [
+                    Ok(_),
+                    Err(LexError::UnterminatedComment { .. }),
+                    Err(LexError::UnreadableInput { .. })
+                ]
+            ),
+            "{:?}",
+            results
+        );
+        end_test("streaming lexer");
+    }
+    #[test]
+    fn test_comments() {
+        start_test("comments");
+        let mut lexer =
+            Lexer::from_string("a + /* one /* two */ */ b # trailing * ) (".to_string());
+        lexer.keep_comments = true;
+        let mut kinds = vec![];
+        while let Some(token) = lexer.next_token() {
+            kinds.push(token.kind);
+        }
+        assert_eq!(kinds, [TokenKind::Ident, TokenKind::Plus, TokenKind::Ident]);
+        let comments: Vec<(&str, usize, usize)> = lexer
+            .comments
+            .iter()
+            .map(|comment| {
+                (
+                    comment.text.as_ref(),
+                    comment.span.start.col_idx(),
+                    comment.span.end.col_idx(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            comments,
+            [("/* one /* two */ */", 4, 23), ("# trailing * ) (", 26, 42)]
+        );
+
+        let mut lexer = Lexer::from_file("./src/test/file_parsing/test2.txt")
+            .expect("failed file read while testing comments");
+        lexer.keep_comments = true;
+        while lexer.next_token().is_some() {}
+        let block = &lexer.comments[2];
+        assert_eq!(block.text, "/* evaluates\nto 10 */");
+        match (&block.span.start, &block.span.end) {
+            (Loc::File { ln: 3, col: 6, .. }, Loc::File { ln: 4, col: 9, .. }) => (),
+            otherwise => panic!("unexpected span for block comment: {:?}", otherwise),
+        }
+        // unless asked for, comments are skipped without being stored
+        let mut lexer = Lexer::new("a # skipped");
+        while lexer.next_token().is_some() {}
+        assert!(lexer.comments.is_empty());
+
+        let mut lexer = Lexer::from_string("a /* never /* closed */".to_string());
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Ident);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Error);
+        assert!(lexer.next_token().is_none());
+        end_test("comments");
+    }
+    #[test]
+    fn test_lossless_lexing() {
+        start_test("lossless lexing");
+        fn lex_lossless(mut lexer: Lexer) -> Vec<Token> {
+            lexer.keep_trivia = true;
+            let mut tokens = vec![];
+            while let Some(token) = lexer.next_token() {
+                tokens.push(token);
+            }
+            assert_eq!(tokens.last().unwrap().kind, TokenKind::EOF);
+            tokens
+        }
+        for input in [
+            "",
+            "  ",
+            "a+b",
+            " f( x ,\t1_000 )  ==  0x1F # done ",
+            "a = 1\r\n  b=/* two /* nested */ */ 2\r\n\r\n",
+            "6.022e23*(1e-9 - c)   /* trailing */\n",
+        ] {
+            let tokens = lex_lossless(Lexer::from_string(input.to_string()));
+            assert_eq!(source_text(&tokens), input);
+        }
+
+        let tokens = lex_lossless(Lexer::from_string(" a # note\n".to_string()));
+        let trivia: Vec<Vec<(TriviaKind, &str)>> = tokens
+            .iter()
+            .map(|token| {
+                token
+                    .trivia
+                    .iter()
+                    .map(|trivia| (trivia.kind, trivia.text.as_ref()))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            trivia,
+            [
+                vec![(TriviaKind::Whitespace, " ")],
+                vec![
+                    (TriviaKind::Whitespace, " "),
+                    (TriviaKind::LineComment, "# note")
+                ],
+                vec![],
+            ]
+        );
+
+        let path = "./src/test/file_parsing/test2.txt";
+        let tokens = lex_lossless(Lexer::from_file(path).expect("failed file read"));
+        assert_eq!(source_text(&tokens), std::fs::read_to_string(path).unwrap());
+
+        // trivia is dropped outside of lossless mode
+        let mut lexer = Lexer::from_string(" a /* b */".to_string());
+        assert!(lexer.next_token().unwrap().trivia.is_empty());
+        assert!(lexer.next_token().is_none());
+        end_test("lossless lexing");
+    }
+    #[test]
+    fn test_lexer_iterator() {
+        start_test("lexer iterator");
+        let input = String::from("θ₁ = 0x1F $ √x  # done");
+        let mut values = vec![];
+        let mut errors = vec![];
+        for result in Lexer::new(&input) {
+            match result {
+                Ok(token) => values.push((token.kind, token.value().to_string())),
+                Err(error) => errors.push(error),
+            }
+        }
+        let expected: Vec<(TokenKind, String)> = [
+            (TokenKind::Ident, "θ₁"),
+            (TokenKind::Equals, "="),
+            (TokenKind::NumLit, "0x1F"),
+            (TokenKind::Sqrt, "√"),
+            (TokenKind::Ident, "x"),
+        ]
+        .iter()
+        .map(|(kind, value)| (*kind, value.to_string()))
+        .collect();
+        assert_eq!(values, expected);
+        assert!(matches!(
+            errors.as_slice(),
+            [LexError::UnexpectedChar { char: '$', .. }]
+        ));
+
+        // token values borrow from the input instead of copying it
+        let token = Lexer::new(&input).next().unwrap().unwrap();
+        let range = input.as_bytes().as_ptr_range();
+        assert!(range.contains(&token.value().as_ptr()));
+
+        // lossless mode round trips borrowed input
+        let input = input.replace("$ ", "");
+        let mut lexer = Lexer::new(&input);
+        lexer.keep_trivia = true;
+        let tokens: Vec<Token> = lexer.map(|token| token.unwrap()).collect();
+        assert_eq!(tokens.last().unwrap().kind, TokenKind::EOF);
+        assert_eq!(source_text(&tokens), input);
+        end_test("lexer iterator");
+    }
+    #[test]
+    fn test_lexer_error_recovery() {
+        start_test("lexer error recovery");
+        fn lex_all(input: &str) -> Vec<(TokenKind, String)> {
+            let mut lexer = Lexer::from_string(input.to_string());
+            let mut tokens = vec![];
+            while let Some(token) = lexer.next_token() {
+                tokens.push((token.kind, token.to_string()));
+            }
+            tokens
+        }
+        use TokenKind::{Error, NumLit, Plus};
+        let expected: Vec<(TokenKind, String)> = [
+            (NumLit, "1"),
+            (Error, "$"),
+            (NumLit, "2"),
+            (Error, "@"),
+            (Error, "0x1G"),
+            (Plus, "+"),
+            (Error, "3.4.5"),
+            (Error, "&"),
+            (NumLit, "6"),
+            (Plus, "+"),
+            (Error, "/* open"),
+        ]
+        .iter()
+        .map(|(kind, value)| (*kind, value.to_string()))
+        .collect();
+        assert_eq!(lex_all("1 $ 2 @ 0x1G + 3.4.5 & 6 + /* open"), expected);
+
+        // a malformed literal is underlined as a whole, like an unterminated comment
+        let spans: Vec<(String, String)> = Lexer::from_string("x + 0x1G + 1.2.3".to_string())
+            .filter_map(Result::err)
+            .flat_map(|error| error.problem_spans())
+            .map(|span| (span.start.to_string(), span.end.to_string()))
+            .collect();
+        assert_eq!(
+            spans,
+            [("repl:1:5", "repl:1:9"), ("repl:1:12", "repl:1:17")]
+                .map(|(start, end)| (start.to_string(), end.to_string()))
+        );
+
+        // error tokens keep their source text in lossless mode
+        let mut lexer = Lexer::from_string("a $ 1x2".to_string());
+        lexer.keep_trivia = true;
+        let mut tokens = vec![];
+        while let Some(token) = lexer.next_token() {
+            tokens.push(token);
+        }
+        assert_eq!(source_text(&tokens), "a $ 1x2");
+
+        // the parser drops the rest of a bad line and carries on with the next one
+        let eval_env = EvalEnv::new();
+        let mut parser = Parser::from_string("1 + $ + @\n2 + 3".to_string());
+        assert!(parser.parse(&eval_env).is_err());
+        let expr = parser.parse(&eval_env).expect("failed to parse expression");
+        assert_eq!(expr.to_string(), "2+3");
+        end_test("lexer error recovery");
+    }
+    #[test]
+    fn test_precedence() {
+        start_test("precedence and associativity");
+        // ^ is right-associative
+        test_shape("2^3^2", "(^ 2 (^ 3 2))");
+        test_shape("a^b^c^d", "(^ a (^ b (^ c d)))");
+        test_shape("(a^b)^c", "(^ (^ a b) c)");
+        test_shape("2^3²", "(^ 2 (^ 3 2))");
+        test_shape("x²^2", "(^ (^ x 2) 2)");
+        // everything else is left-associative
+        test_shape("1-2-3", "(- (- 1 2) 3)");
+        test_shape("a-b+c", "(+ (- a b) c)");
+        test_shape("8/4/2", "(/ (/ 8 4) 2)");
+        test_shape("a/b*c", "(* (/ a b) c)");
+        test_shape("a==b!=c", "(!= (== a b) c)");
+        test_shape("a&&b&&c", "(&& (&& a b) c)");
+        test_shape("a||b||c", "(|| (|| a b) c)");
+        // precedence between levels
+        test_shape("2*3^2", "(* 2 (^ 3 2))");
+        test_shape("2^3*2", "(* (^ 2 3) 2)");
+        test_shape("x²+1", "(+ (^ x 2) 1)");
+        test_shape("1+2*3-4/2", "(- (+ 1 (* 2 3)) (/ 4 2))");
+        test_shape("a*b+c*d^e^f/g", "(+ (* a b) (/ (* c (^ d (^ e f))) g))");
+        test_shape("a+b<c*d", "(< (+ a b) (* c d))");
+        test_shape("a<b==c<d", "(== (< a b) (< c d))");
+        test_shape("a==b&&c!=d", "(&& (== a b) (!= c d))");
+        test_shape("a||b&&c", "(|| a (&& b c))");
+        test_shape("a&&b||c&&d", "(|| (&& a b) (&& c d))");
+        test_shape(
+            "1+2==3&&4>3||false",
+            "(|| (&& (== (+ 1 2) 3) (> 4 3)) false)",
+        );
+        test_shape("x=1+2*3", "(= x (+ 1 (* 2 3)))");
+        test_shape("f(a,b)=a^b^2", "(= f(a,b) (^ a (^ b 2)))");
+        // prefix operators only take the operand that follows
+        test_shape("!a==b", "(== (! a) b)");
+        test_shape("!(a==b)", "(! (== a b))");
+        test_shape("√4^2", "(^ sqrt(4) 2)");
+        test_shape("√(4^2)", "sqrt((^ 4 2))");
+        // parentheses and arguments start over at the lowest precedence
+        test_shape("(1+2)*3", "(* (+ 1 2) 3)");
+        test_shape("2*(3-4)^(2*3)", "(* 2 (^ (- 3 4) (* 2 3)))");
+        test_shape("f(a+b,c*d^e)-1", "(- f((+ a b),(* c (^ d e))) 1)");
+
+        fn test_value(input: &str, expected: f64) {
+            let mut parser = Parser::from_string(input.to_string());
+            let mut eval_env = EvalEnv::new();
+            let expr = parser.parse(&eval_env).expect("failed to parse expression");
+            let val = expr
+                .eval(&mut eval_env)
+                .expect("failed to evaluate expression")
+                .expect_val("could not evaluate expr");
+            assert_eq!(
+                val, expected,
+                "evaluating {} did not yield {}",
+                input, expected
+            );
+        }
+        test_value("2^3^2", 512.0);
+        test_value("2^2^3", 256.0);
+        test_value("(2^3)^2", 64.0);
+        test_value("1-2-3", -4.0);
+        test_value("100/10/5", 2.0);
+        test_value("2*3^2", 18.0);
+        test_value("10-2*3+4/2", 6.0);
+        test_value("2^3²", 512.0);
+
+        // assignment is not associative and only allowed at the top level, f(a=1) names an
+        // argument instead
+        for input in [
+            "a=b=1", "(a=1)", "f(a=b=1)", "f(1=a)", "f((a=1))", "1+a=2", "2^=3", "*2", "1+",
+        ] {
+            let mut parser = Parser::from_string(input.to_string());
+            assert!(
+                parser.parse(&EvalEnv::new()).is_err(),
+                "parsed {}, expected to fail",
+                input
+            );
+        }
+        end_test("precedence and associativity");
+    }
+    #[test]
+    fn test_unary_ops() {
+        start_test("unary operators");
+        test_shape("-x", "(- x)");
+        test_shape("-2^2", "(- (^ 2 2))");
+        test_shape("-x²", "(- (^ x 2))");
+        test_shape("(-2)^2", "(^ (- 2) 2)");
+        test_shape("2^-1", "(^ 2 (- 1))");
+        test_shape("2*-3", "(* 2 (- 3))");
+        test_shape("-2*3", "(* (- 2) 3)");
+        test_shape("a- -b", "(- a (- b))");
+        test_shape("--x", "(- (- x))");
+        test_shape("+x-+y", "(- (+ x) (+ y))");
+        test_shape("-(a+b)", "(- (+ a b))");
+        test_shape("f(-1,-x)", "f((- 1),(- x))");
+        test_shape("!a&&-b<c", "(&& (! a) (< (- b) c))");
+        test_shape("x=-1", "(= x (- 1))");
+
+        let mut eval_env = EvalEnv::new();
+        // symbolic evaluation keeps the operator around unknowns
+        for (input, expected) in [
+            ("-x", "-x"),
+            ("+x", "x"),
+            ("-(a+b)", "-(a+b)"),
+            ("-(2+3)", "-5"),
+        ] {
+            let val = eval_on_string(input, &mut eval_env).expect("failed to evaluate expression");
+            assert_eq!(val.to_string(), expected, "evaluating {}", input);
+        }
+        eval_on_string("x=3", &mut eval_env);
+        eval_on_string("f(y)=y*2", &mut eval_env);
+        for (input, expected) in [
+            ("-2^2", -4.0),
+            ("(-2)^2", 4.0),
+            ("2^-1", 0.5),
+            ("--2", 2.0),
+            ("+2", 2.0),
+            ("-x", -3.0),
+            ("-x²", -9.0),
+            ("2*-x", -6.0),
+            ("f(-1)", -2.0),
+            ("-f(x)+1", -5.0),
+        ] {
+            let val = eval_on_string(input, &mut eval_env)
+                .expect("failed to evaluate expression")
+                .expect_val("could not evaluate expr");
+            assert_eq!(
+                val, expected,
+                "evaluating {} did not yield {}",
+                input, expected
+            );
+        }
+        let val =
+            eval_on_string("!!(1 < 2)", &mut eval_env).expect("failed to evaluate expression");
+        assert!(val.expect_bool("could not evaluate expr"));
+        // negation needs a number, logical not a bool
+        assert!(eval_on_string("-true", &mut eval_env).is_none());
+        assert!(eval_on_string("+(1<2)", &mut eval_env).is_none());
+        assert!(eval_on_string("!1", &mut eval_env).is_none());
+        end_test("unary operators");
+    }
+    #[test]
+    fn test_implicit_mult() {
+        start_test("implicit multiplication");
+        test_shape("2x", "(* 2 x)");
+        test_shape("2.5x", "(* 2.5 x)");
+        test_shape("3(a+b)", "(* 3 (+ a b))");
+        test_shape("(a+b)(a-b)", "(* (+ a b) (- a b))");
+        test_shape("2 pi r", "(* (* 2 pi) r)");
+        test_shape("2(3)(4)", "(* (* 2 3) 4)");
+        test_shape("2x^2", "(* 2 (^ x 2))");
+        test_shape("2x²", "(* 2 (^ x 2))");
+        test_shape("2^3x", "(* (^ 2 3) x)");
+        test_shape("1/2x", "(/ 1 (* 2 x))");
+        test_shape("2x*3", "(* (* 2 x) 3)");
+        test_shape("2x+3y", "(+ (* 2 x) (* 3 y))");
+        test_shape("-2x", "(* (- 2) x)");
+        test_shape("2√x", "(* 2 sqrt(x))");
+        test_shape("2e", "(* 2 e)");
+        test_shape("2e3x", "(* 2000 x)");
+        test_shape("x2", "x2");
+        // names without a value are still calls
+        test_shape("f(x)", "f(x)");
+        test_shape("2f(x)", "(* 2 f(x))");
+
+        let mut eval_env = EvalEnv::new();
+        eval_on_string("x=3", &mut eval_env);
+        eval_on_string("r=1", &mut eval_env);
+        eval_on_string("f(y)=y+1", &mut eval_env);
+        eval_on_string("g(x)=x(x+1)", &mut eval_env);
+        for (input, expected) in [
+            ("2x", 6.0),
+            ("2 pi r", 2.0 * std::f64::consts::PI),
+            ("(x+1)(x-1)", 8.0),
+            ("x(x+1)", 12.0),
+            ("1/2x", 1.0 / 6.0),
+            ("2x^2", 18.0),
+            ("f(x)", 4.0),
+            ("2f(x)", 8.0),
+            ("g(2)", 6.0),
+        ] {
+            let val = eval_on_string(input, &mut eval_env)
+                .expect("failed to evaluate expression")
+                .expect_val("could not evaluate expr");
+            assert_eq!(
+                val, expected,
+                "evaluating {} did not yield {}",
+                input, expected
+            );
+        }
+        // a number can't be the right side of a juxtaposition, and hex literals still end at
+        // letters
+        for input in ["ab 123", "2 3", "x 2", "0x1Fg", "2 true"] {
+            assert!(
+                Parser::from_string(input.to_string())
+                    .parse(&eval_env)
+                    .is_err(),
+                "parsed {}, expected to fail",
+                input
+            );
+        }
+        end_test("implicit multiplication");
+    }
+    #[test]
+    fn test_expr_spans() {
+        start_test("expression spans");
+        fn cols(expr: &Expr) -> (usize, usize) {
+            let span = expr.span.as_ref().expect("parsed expression without span");
+            (span.start.col_idx(), span.end.col_idx())
+        }
+        let mut parser = Parser::from_string("abc+f(x,2)*(3-y)".to_string());
+        let expr = parser.parse(&EvalEnv::new()).unwrap();
+        assert_eq!(cols(&expr), (0, 16));
+        let ExprKind::BinOp { left, right, .. } = &expr.kind else {
+            panic!("expected binop, got {:?}", expr)
+        };
+        assert_eq!(cols(left), (0, 3));
+        assert_eq!(cols(right), (4, 16));
+        let ExprKind::BinOp {
+            left: fun,
+            right: group,
+            ..
+        } = &right.kind
+        else {
+            panic!("expected binop, got {:?}", right)
+        };
+        assert_eq!(cols(fun), (4, 10));
+        assert_eq!(cols(group), (11, 16));
+        let ExprKind::Fun { params, .. } = &fun.kind else {
+            panic!("expected functor, got {:?}", fun)
+        };
+        assert_eq!(cols(&params[0]), (6, 7));
+        assert_eq!(cols(&params[1]), (8, 9));
+        assert_eq!(
+            format_problem_area(right.span.as_ref().unwrap()),
+            "    abc+f(x,2)*(3-y)\n        ^^^^^^^^^^^^\n"
+        );
+
+        // evaluated expressions keep the span of the node they replace
+        let val = expr
+            .eval(&mut EvalEnv::new())
+            .expect("failed to evaluate expression");
+        assert_eq!(cols(&val), (0, 16));
+        end_test("expression spans");
+    }
+    #[test]
+    fn test_logic_eval() {
+        start_test("logic evaluation");
+        fn test_logic_eval_on_string(input: &str, expected: Option<bool>) {
+            let mut eval_env = EvalEnv::new();
+            let mut parser = Parser::from_string(input.to_string());
+            let expr = parser.parse(&eval_env).expect("failed to parse expression");
+            let val = expr.eval(&mut eval_env);
+            match expected {
+                Some(expected) => assert_eq!(
+                    val.expect("failed to evaluate expression")
+                        .expect_bool("could not evaluate expr"),
+                    expected,
+                    "evaluating {} did not yield {}",
+                    input,
+                    expected
+                ),
+                None => assert!(val.is_none(), "expected evaluating {} to fail", input),
+            }
+        }
+        test_logic_eval_on_string("1<2", Some(true));
+        test_logic_eval_on_string("2<=2", Some(true));
+        test_logic_eval_on_string("3>4", Some(false));
+        test_logic_eval_on_string("3>=4", Some(false));
+        test_logic_eval_on_string("1!=2", Some(true));
+        test_logic_eval_on_string("true!=true", Some(false));
+        test_logic_eval_on_string("true&&false", Some(false));
+        test_logic_eval_on_string("true||false", Some(true));
+        test_logic_eval_on_string("!true", Some(false));
+        test_logic_eval_on_string("!(1>2)", Some(true));
+        test_logic_eval_on_string("1+1==2", Some(true));
+        test_logic_eval_on_string("1<2==2<3", Some(true));
+        test_logic_eval_on_string("1<2&&2<3", Some(true));
+        test_logic_eval_on_string("false&&true||true", Some(true));
+        test_logic_eval_on_string("true||false&&false", Some(true));
+        test_logic_eval_on_string("!false&&true", Some(true));
+        test_logic_eval_on_string("2*3>=6&&!(1==2)", Some(true));
+        test_logic_eval_on_string("1+2*3<2^3||false", Some(true));
+
+        test_logic_eval_on_string("true+1", None);
+        test_logic_eval_on_string("true*false", None);
+        test_logic_eval_on_string("1&&true", None);
+        test_logic_eval_on_string("!2", None);
+        test_logic_eval_on_string("true<false", None);
+        test_logic_eval_on_string("1==true", None);
+        test_logic_eval_on_string("x||2", None);
+
+        let mut eval_env = EvalEnv::new();
+        for input in ["x<1", "x&&true", "!x"] {
+            let mut parser = Parser::from_string(input.to_string());
+            let expr = parser.parse(&eval_env).expect("failed to parse expression");
+            let val = expr
+                .eval(&mut eval_env)
+                .expect("failed to evaluate expression");
+            assert_eq!(val.to_string(), input);
+        }
+        let mut lexer = Lexer::from_string("a & b".to_string());
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Ident);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Error);
+        assert_eq!(lexer.next_token().unwrap().kind, TokenKind::Ident);
+        end_test("logic evaluation");
+    }
+    #[test]
     fn test_bool_eval() {
         let mut eval_env = EvalEnv::new();
 
         fn test_bool_eval_on_string(input: &str, eval_env: &mut EvalEnv, expected: Option<bool>) {
             let mut parser = Parser::from_string(input.to_string());
             let expr = parser.parse(eval_env).expect("failed to parse expression");
-            let val = expr.eval(eval_env);
+            let val = expr.eval(eval_env).expect("failed to evaluate expression");
             println!("{} evaluated to {}", expr, val);
             if let Some(expected) = expected {
                 assert_eq!(
@@ -292,4 +1252,776 @@ mod tests {
         test_bool_eval_on_string("a==a", &mut eval_env, Some(true));
         end_test("bool evaluation");
     }
+    #[test]
+    fn test_conditionals() {
+        start_test("conditionals");
+        let (sink, mut eval_env) = collecting_env();
+        eval_on_string("abs(x) = if x < 0 then -x else x", &mut eval_env)
+            .expect("failed to define abs");
+        eval_on_string(
+            "tax(x) = piecewise(0 if x < 10000, (x - 10000) / 10 if x < 50000, 4000 + (x - 50000) / 5)",
+            &mut eval_env,
+        )
+        .expect("failed to define tax");
+        for (input, expected) in [
+            ("abs(-3)", 3.0),
+            ("abs(2)", 2.0),
+            ("2 * if 1 < 2 then 3 else 4", 6.0),
+            ("if false then 1 else 2 + 3", 5.0),
+            ("tax(5000)", 0.0),
+            ("tax(20000)", 1000.0),
+            ("tax(60000)", 6000.0),
+            ("piecewise(1 if false, 2 if 1 == 1)", 2.0),
+            // only the selected branch is evaluated
+            ("if true then 1 else 1 + true", 1.0),
+            ("piecewise(1 + true if false, 2)", 2.0),
+        ] {
+            let val = eval_on_string(input, &mut eval_env)
+                .expect("failed to evaluate expression")
+                .expect_val("could not evaluate expr");
+            assert_eq!(
+                val, expected,
+                "evaluating {} did not yield {}",
+                input, expected
+            );
+        }
+        assert!(sink.take().is_empty());
+
+        // a symbolic condition leaves the expression unevaluated
+        let val = eval_on_string("if y > 0 then 1 else y", &mut eval_env)
+            .expect("failed to evaluate expression");
+        assert_eq!(val.to_string(), "if y>0 then 1 else y");
+        let val = eval_on_string("piecewise(1 if false, y if y > 0, 2)", &mut eval_env)
+            .expect("failed to evaluate expression");
+        assert_eq!(val.to_string(), "piecewise(y if y>0, 2)");
+        eval_on_string("y = 3", &mut eval_env);
+        let val = eval_on_string("if y > 0 then 1 else y", &mut eval_env)
+            .expect("failed to evaluate expression");
+        assert_eq!(val.expect_val("could not evaluate expr"), 1.0);
+
+        // conditions must be booleans, and a piecewise without a matching branch has no value
+        assert!(eval_on_string("if 1 then 2 else 3", &mut eval_env).is_none());
+        assert!(eval_on_string("piecewise(1 if false)", &mut eval_env).is_none());
+        assert_eq!(sink.take().len(), 2);
+        for input in [
+            "if a = 1 then 2 else 3",
+            "if true then 2",
+            "piecewise(1, 2 if true)",
+            "2 if true",
+        ] {
+            assert!(
+                Parser::from_string(input.to_string())
+                    .with_diag(eval_env.diag.clone())
+                    .parse(&eval_env)
+                    .is_err(),
+                "parsed {}, expected to fail",
+                input
+            );
+        }
+        end_test("conditionals");
+    }
+    #[test]
+    fn test_lambdas() {
+        start_test("lambdas");
+        let mut eval_env = EvalEnv::new();
+        for def in [
+            "sq = x -> x^2",
+            "mul = (a, b) -> a*b",
+            "inc(x) = x + 1",
+            "twice(g, x) = g(g(x))",
+            "adder(n) = x -> x + n",
+            "add2 = adder(2)",
+            "h = compose(sq, inc)",
+            "incmul = compose(inc, (a, b) -> a*b)",
+        ] {
+            eval_on_string(def, &mut eval_env).expect("failed to evaluate definition");
+        }
+        for (input, expected) in [
+            ("sq(3)", 9.0),
+            ("mul(2, 3)", 6.0),
+            ("apply(inc, 3)", 4.0),
+            ("apply(x -> 2x, 5)", 10.0),
+            ("apply(mul, 2, 4)", 8.0),
+            ("twice(sq, 3)", 81.0),
+            ("twice(x → x + 1, 0)", 2.0),
+            ("add2(5)", 7.0),
+            ("h(2)", 9.0),
+            ("incmul(2, 5)", 11.0),
+        ] {
+            let val = eval_on_string(input, &mut eval_env)
+                .expect("failed to evaluate expression")
+                .expect_val("could not evaluate expr");
+            assert_eq!(
+                val, expected,
+                "evaluating {} did not yield {}",
+                input, expected
+            );
+        }
+        for (input, expected) in [
+            ("sq", "x->x^2"),
+            ("mul", "(a,b)->a*b"),
+            ("inc", "x->x+1"),
+            ("add2", "x->x+2"),
+            ("h", "x->(x+1)^2"),
+        ] {
+            let val = eval_on_string(input, &mut eval_env).expect("failed to evaluate expression");
+            assert_eq!(val.to_string(), expected);
+        }
+        test_shape("(a, b) -> a + b * 2", "(a,b)->a+b*2");
+        test_shape("f(x -> x, 1)", "f(x->x,1)");
+
+        for input in ["sq(1, 2)", "sq * 2", "1 + inc"] {
+            assert!(
+                eval_on_string(input, &mut eval_env).is_none(),
+                "evaluated {}, expected to fail",
+                input
+            );
+        }
+        for input in ["(a, 1) -> a", "x -> y = 1", "2 -> 3"] {
+            assert!(
+                Parser::from_string(input.to_string())
+                    .parse(&eval_env)
+                    .is_err(),
+                "parsed {}, expected to fail",
+                input
+            );
+        }
+        end_test("lambdas");
+    }
+    #[test]
+    fn test_lists() {
+        start_test("lists");
+        let kinds = |input: &str| -> Vec<TokenKind> {
+            Lexer::new(input).map(|token| token.unwrap().kind).collect()
+        };
+        assert_eq!(
+            kinds("1..10"),
+            [TokenKind::NumLit, TokenKind::DotDot, TokenKind::NumLit]
+        );
+        assert_eq!(kinds("1.5"), [TokenKind::NumLit]);
+        assert_eq!(
+            kinds("xs[0]"),
+            [
+                TokenKind::Ident,
+                TokenKind::OpenBracket,
+                TokenKind::NumLit,
+                TokenKind::CloseBracket
+            ]
+        );
+
+        let mut eval_env = EvalEnv::new();
+        for def in ["xs = [1, 2, 3]", "f(x) = x^2 + 1", "p = (1, true)"] {
+            eval_on_string(def, &mut eval_env).expect("failed to evaluate definition");
+        }
+        for (input, expected) in [
+            ("[]", "[]"),
+            ("[1, 2 + 3, -1]", "[1,5,-1]"),
+            ("1..5", "[1,2,3,4,5]"),
+            ("3..1", "[]"),
+            ("(1, 2)", "(1,2)"),
+            ("p", "(1,true)"),
+            ("xs + 1", "[2,3,4]"),
+            ("2 xs", "[2,4,6]"),
+            ("xs * [4, 5, 6]", "[4,10,18]"),
+            ("-xs", "[-1,-2,-3]"),
+            ("xs < 2", "[true,false,false]"),
+            ("f(0..3)", "[1,2,5,10]"),
+            ("map(x -> 2x, xs)", "[2,4,6]"),
+            ("[a, 1] * 2", "[a*2,2]"),
+            ("xs == [1, 2, 3]", "true"),
+            ("(1, 2) != (1, 2)", "false"),
+        ] {
+            let val = eval_on_string(input, &mut eval_env).expect("failed to evaluate expression");
+            assert_eq!(val.to_string(), expected, "evaluating {}", input);
+        }
+        for (input, expected) in [
+            ("xs[0]", 1.0),
+            ("xs[1 + 1]", 3.0),
+            ("(1..10)[4]", 5.0),
+            ("-xs[0]", -1.0),
+            ("p[0]", 1.0),
+            ("len(xs)", 3.0),
+            ("len([])", 0.0),
+            ("len(p)", 2.0),
+        ] {
+            let val = eval_on_string(input, &mut eval_env)
+                .expect("failed to evaluate expression")
+                .expect_val("could not evaluate expr");
+            assert_eq!(val, expected, "evaluating {}", input);
+        }
+        test_shape("xs[i] + 1", "(+ xs[i] 1)");
+        test_shape("2x[0]", "(* 2 x[0])");
+
+        for input in [
+            "xs[3]",
+            "xs[-1]",
+            "xs[0.5]",
+            "2[0]",
+            "xs + [1, 2]",
+            "xs and true",
+            "1..(1/0)",
+            "(1, 2) + 1",
+            "2 * p",
+            "-(1, 2)",
+            "xs + (1, 2)",
+        ] {
+            assert!(
+                eval_on_string(input, &mut eval_env).is_none(),
+                "evaluated {}, expected to fail",
+                input
+            );
+        }
+        for input in ["[1, 2", "xs[0", "1 . 2"] {
+            assert!(
+                Parser::from_string(input.to_string())
+                    .parse(&eval_env)
+                    .is_err(),
+                "parsed {}, expected to fail",
+                input
+            );
+        }
+        end_test("lists");
+    }
+    #[test]
+    fn test_matrices() {
+        start_test("matrices");
+        let mut eval_env = EvalEnv::new();
+        for def in [
+            "A = [[1, 2], [3, 4]]",
+            "B = [[0, 1], [1, 0]]",
+            "S = [[a, b], [c, d]]",
+            "R = [[1, 2, 3], [4, 5, 6]]",
+            "M = [[2, 0, 0, 1], [0, 3, 0, 0], [0, 0, 4, 0], [1, 0, 0, 2]]",
+        ] {
+            eval_on_string(def, &mut eval_env).expect("failed to evaluate definition");
+        }
+        for (input, expected) in [
+            ("A", "[[1,2],[3,4]]"),
+            ("[[1], [2]]", "[[1],[2]]"),
+            ("[[1], [2, 3]]", "[[1],[2,3]]"),
+            ("A + B", "[[1,3],[4,4]]"),
+            ("A - A", "[[0,0],[0,0]]"),
+            ("A * B", "[[2,1],[4,3]]"),
+            ("2A", "[[2,4],[6,8]]"),
+            ("A / 2", "[[0.5,1],[1.5,2]]"),
+            // numbers apply to each entry, as they do to the items of a list
+            ("[[1, 2], [3, 4]] + 1", "[[2,3],[4,5]]"),
+            ("1 + [[1, 2], [3, 4]]", "[[2,3],[4,5]]"),
+            ("A - 1", "[[0,1],[2,3]]"),
+            ("10 - A", "[[9,8],[7,6]]"),
+            ("2 / [[1, 2], [4, 8]]", "[[2,1],[0.5,0.25]]"),
+            ("A + x", "[[1+x,2+x],[3+x,4+x]]"),
+            ("-A", "[[-1,-2],[-3,-4]]"),
+            ("A * [1, 1]", "[3,7]"),
+            ("[1, 1] * A", "[4,6]"),
+            ("A^2", "[[7,10],[15,22]]"),
+            ("A^0", "[[1,0],[0,1]]"),
+            ("A^-1", "[[-2,1],[1.5,-0.5]]"),
+            ("transpose(R)", "[[1,4],[2,5],[3,6]]"),
+            ("inverse(A)", "[[-2,1],[1.5,-0.5]]"),
+            ("solve(A, [5, 11])", "[1,2]"),
+            ("solve(A, [[5, 1], [11, 3]])", "[[1,1],[2,0]]"),
+            ("identity(2)", "[[1,0],[0,1]]"),
+            ("zeros(2, 3)", "[[0,0,0],[0,0,0]]"),
+            ("A[1]", "[3,4]"),
+            ("R * transpose(R)", "[[14,32],[32,77]]"),
+            ("inverse(M)", "[[0.6666666666666666,0,0,-0.3333333333333333],[0,0.3333333333333333,0,0],[0,0,0.25,0],[-0.3333333333333333,0,0,0.6666666666666666]]"),
+            ("solve(M, [3, 3, 4, 3])", "[1,1,1,1]"),
+            // symbolic entries
+            ("S * B", "[[b,a],[d,c]]"),
+            ("S * [x, y]", "[a*x+b*y,c*x+d*y]"),
+            ("det(S)", "a*d-b*c"),
+            ("transpose(S)", "[[a,c],[b,d]]"),
+            ("inverse(S)", "[[d/(a*d-b*c),-b/(a*d-b*c)],[-c/(a*d-b*c),a/(a*d-b*c)]]"),
+            ("S + A", "[[a+1,b+2],[c+3,d+4]]"),
+            ("rank(S)", "rank(S)"),
+            ("A == [[1, 2], [3, 4]]", "true"),
+            ("A != B", "true"),
+        ] {
+            let val = eval_on_string(input, &mut eval_env).expect("failed to evaluate expression");
+            assert_eq!(val.to_string(), expected, "evaluating {}", input);
+        }
+        for (input, expected) in [
+            ("det(A)", -2.0),
+            ("det([[2, 0, 1], [1, 3, 2], [1, 1, 2]])", 6.0),
+            ("det(M)", 36.0),
+            ("rank(A)", 2.0),
+            ("rank([[1, 2], [2, 4]])", 1.0),
+            ("rank(R)", 2.0),
+            ("A[1][0]", 3.0),
+            ("len(R)", 2.0),
+        ] {
+            let val = eval_on_string(input, &mut eval_env)
+                .expect("failed to evaluate expression")
+                .expect_val("could not evaluate expr");
+            assert!(
+                (val - expected).abs() < 1e-9,
+                "evaluating {} gave {}, expected {}",
+                input,
+                val,
+                expected
+            );
+        }
+
+        for input in [
+            "A + R",
+            "A * A * R * A",
+            "A + [1, 2]",
+            "A < B",
+            "R^2",
+            "A^0.5",
+            "det(R)",
+            "inverse([[1, 2], [2, 4]])",
+            "inverse([[1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0], [0, 0, 2, 0]])",
+            "solve(A, [1, 2, 3])",
+            "identity(0)",
+            "zeros(1.5)",
+        ] {
+            assert!(
+                eval_on_string(input, &mut eval_env).is_none(),
+                "evaluated {}, expected to fail",
+                input
+            );
+        }
+        end_test("matrices");
+    }
+    #[test]
+    fn test_let() {
+        start_test("let");
+        let mut eval_env = EvalEnv::new();
+        for def in [
+            "x = 3",
+            "y = 4",
+            "norm(a, b) = let r2 = a^2 + b^2 in sqrt(r2)",
+            "g(t) = let s = t + x in s * s",
+        ] {
+            eval_on_string(def, &mut eval_env).expect("failed to evaluate definition");
+        }
+        for (input, expected) in [
+            ("let r2 = x^2 + y^2 in sqrt(r2) * r2", 125.0),
+            ("let x = 10 in x + 1", 11.0),
+            ("let a = 1 in let b = a + 1 in a + b", 3.0),
+            ("let a = 2 in let a = a * 3 in a", 6.0),
+            ("norm(3, 4)", 5.0),
+            ("g(1)", 16.0),
+            ("let f = t -> 2t in f(4)", 8.0),
+            ("let r = 2 in r(1 + 1)", 4.0),
+            ("1 + let a = 2 in a * a", 5.0),
+            ("(let a = 2 in a) + 1", 3.0),
+        ] {
+            let val = eval_on_string(input, &mut eval_env)
+                .expect("failed to evaluate expression")
+                .expect_val("could not evaluate expr");
+            assert_eq!(val, expected, "evaluating {}", input);
+        }
+        // the binding does not outlive its body
+        assert!(!eval_env.vars.contains_key("r2"));
+        assert_eq!(eval_env.vars["x"].to_string(), "3");
+        for (input, expected) in [
+            ("let s = a + b in s * c", "(a+b)*c"),
+            ("let s = z in s + 1", "z+1"),
+            ("t -> let u = t + x in u", "t->let u = t+3 in u"),
+        ] {
+            let val = eval_on_string(input, &mut eval_env).expect("failed to evaluate expression");
+            assert_eq!(val.to_string(), expected, "evaluating {}", input);
+        }
+
+        let expr = Parser::from_string("let r = p + q in r * w".to_string())
+            .parse(&eval_env)
+            .expect("failed to parse let");
+        assert_eq!(expr.get_var_names(), ["p", "q", "w"]);
+        test_shape("let a = 1 in a + 2", "let a = 1 in a+2");
+
+        for input in [
+            "let 1 = 2 in 3",
+            "let a = 1",
+            "let a 1 in a",
+            "let a = b = 1 in a",
+            "f(x) = let a = x in a + c",
+            "f(x) = let x = 1 in x",
+        ] {
+            assert!(
+                Parser::from_string(input.to_string())
+                    .parse(&eval_env)
+                    .is_err(),
+                "parsed {}, expected to fail",
+                input
+            );
+        }
+        end_test("let");
+    }
+    #[test]
+    fn test_recursion() {
+        start_test("recursion");
+        let (sink, mut eval_env) = collecting_env();
+        for def in [
+            "fact(n) = if n <= 1 then 1 else n * fact(n - 1)",
+            "fib(n) = piecewise(n if n < 2, fib(n - 1) + fib(n - 2))",
+            "even(n) = if n == 0 then true else !even(n - 1)",
+            "count(n) = 1 + if n == 0 then 0 else count(n - 1)",
+            "forever(n) = if true then forever(n) else 0",
+            "down(n) = if n == 0 then 0 else down(n - 1)",
+        ] {
+            eval_on_string(def, &mut eval_env).expect("failed to evaluate definition");
+        }
+        for (input, expected) in [
+            ("fact(5)", 120.0),
+            ("fact(10)", 3628800.0),
+            ("fib(15)", 610.0),
+            ("count(20)", 21.0),
+            ("fact(3) + fib(3)", 8.0),
+        ] {
+            let val = eval_on_string(input, &mut eval_env)
+                .expect("failed to evaluate expression")
+                .expect_val("could not evaluate expr");
+            assert_eq!(val, expected, "evaluating {}", input);
+        }
+        let val = eval_on_string("even(7)", &mut eval_env).expect("failed to evaluate even(7)");
+        assert_eq!(val.to_string(), "false");
+        // a symbolic argument leaves the recursion unevaluated
+        let val = eval_on_string("fact(k)", &mut eval_env).expect("failed to evaluate fact(k)");
+        assert!(val.to_string().contains("fact(k-1)"), "got {}", val);
+        // free variables of a function are those where it is defined, not those of its caller
+        for stmt in ["c = 10", "h(a) = a + c", "g(c) = h(1) + c"] {
+            eval_on_string(stmt, &mut eval_env).expect("failed to evaluate definition");
+        }
+        let val = eval_on_string("g(5)", &mut eval_env).expect("failed to evaluate g(5)");
+        assert_eq!(val.to_string(), "16");
+        assert!(sink.take().is_empty());
+
+        // the default limit is reported before the stack of a test thread overflows
+        for input in ["forever(1)", "count(40)", "down(200)"] {
+            assert!(
+                eval_on_string(input, &mut eval_env).is_none(),
+                "evaluated {}, expected to fail",
+                input
+            );
+            let diagnostics = sink.take();
+            assert_eq!(diagnostics.len(), 1, "one error for {}", input);
+            assert!(diagnostics[0]
+                .message
+                .contains(&DEFAULT_MAX_DEPTH.to_string()));
+        }
+        eval_env.max_depth = 50;
+        assert!(eval_on_string("count(40)", &mut eval_env).is_some());
+
+        // a script run on a thread with the stack main reserves recurses past the default limit
+        let session = thread::Builder::new()
+            .stack_size(MAX_DEPTH * STACK_PER_CALL)
+            .spawn(|| {
+                let sink = Rc::new(CollectSink::default());
+                let mut repl = Repl::new().with_diag(Diagnoster::new(sink.clone()));
+                repl.run_file("./src/test/file_parsing/recursion.txt")
+                    .expect("failed to read file");
+                sink.take()
+                    .into_iter()
+                    .map(|diagnostic| diagnostic.message)
+                    .collect::<Vec<_>>()
+            })
+            .expect("failed to spawn thread");
+        let messages = session.join().expect("script overflowed the stack");
+        assert!(messages.is_empty(), "got {:?}", messages);
+
+        // without a conditional a recursive definition can never stop
+        for input in [
+            "g(n) = n * g(n - 1)",
+            "g(n) = g(n) + if n < 1 then 0 else 1",
+            "g(n) = piecewise(1 if g(n) > 0, 0)",
+        ] {
+            assert!(
+                Parser::from_string(input.to_string())
+                    .parse(&eval_env)
+                    .is_err(),
+                "parsed {}, expected to fail",
+                input
+            );
+        }
+        end_test("recursion");
+    }
+    #[test]
+    fn test_clauses() {
+        start_test("clauses");
+        let (sink, mut eval_env) = collecting_env();
+        let mut parser =
+            Parser::from_string("fact(0) = 1; fact(n) = n * fact(n - 1); fact(4)".to_string());
+        let program = parser.parse_program(&eval_env);
+        assert!(program.is_ok(), "{:?}", program.errors);
+        let values = program.eval(&mut eval_env);
+        assert_eq!(
+            values[2].as_ref().map(Expr::to_string),
+            Some("24".to_string())
+        );
+        for def in [
+            "fib(0) = 0",
+            "fib(1) = 1",
+            "fib(n) = fib(n - 1) + fib(n - 2)",
+            "len(pair(a, b)) = 2",
+            "swap((a, b)) = (b, a)",
+            "first([x, y]) = x",
+            "sign(-1) = 0",
+            "sign(x) = x",
+            "both(true, true) = true",
+            "both(a, b) = false",
+            "same(x, x) = true",
+            "same(x, y) = false",
+            "g(x) = x + 1",
+            "g(y) = y + 2",
+            "partial(0) = 1",
+        ] {
+            eval_on_string(def, &mut eval_env).expect(def);
+        }
+        assert_eq!(eval_env.funcs["fact"][&1].len(), 2);
+        assert_eq!(eval_env.funcs["fib"][&1].len(), 3);
+        assert_eq!(
+            eval_env.funcs["g"][&1].len(),
+            1,
+            "a clause with the same patterns replaces"
+        );
+        for (input, expected) in [
+            ("fact(5)", "120"),
+            ("fact(k)", "fact(k)"),
+            ("fib(10)", "55"),
+            ("len(pair(1, x))", "2"),
+            ("len([1, 2, 3])", "3"),
+            ("swap((1, 2))", "(2,1)"),
+            ("first([5, 6])", "5"),
+            ("sign(-1)", "0"),
+            ("sign(-2)", "-2"),
+            ("both(true, true)", "true"),
+            ("both(true, false)", "false"),
+            ("same(2, 2)", "true"),
+            ("same(1, 2)", "false"),
+            ("same(a, a)", "true"),
+            ("g(1)", "3"),
+            ("apply(fact, 3)", "6"),
+        ] {
+            let val = eval_on_string(input, &mut eval_env).expect("failed to evaluate expression");
+            assert_eq!(val.to_string(), expected, "evaluating {}", input);
+        }
+        assert!(sink.take().is_empty());
+
+        for input in [
+            "partial(1)",
+            "partial(2 - 1)",
+            "len(triple(1, 2, 3))",
+            "first([1, 2, 3])",
+        ] {
+            assert!(
+                eval_on_string(input, &mut eval_env).is_none(),
+                "evaluated {}, expected to fail",
+                input
+            );
+            let diagnostics = sink.take();
+            assert_eq!(diagnostics.len(), 1);
+            assert!(
+                diagnostics[0].message.starts_with("No clause matches"),
+                "{}",
+                diagnostics[0].message
+            );
+        }
+        eval_on_string("partial(a, b) = a + b", &mut eval_env).unwrap();
+        assert_eq!(eval_env.funcs["partial"][&1].len(), 1);
+        // redefining a clause before the catch-all keeps it reachable
+        eval_on_string("sign(-1) = 5", &mut eval_env).expect("failed to redefine sign(-1)");
+        let val = eval_on_string("sign(-1)", &mut eval_env).expect("failed to evaluate sign(-1)");
+        assert_eq!(val.to_string(), "5");
+        assert!(sink.take().is_empty());
+
+        for input in [
+            "sign(0) = 1",
+            "f(x + 1) = x",
+            "f(2 * y) = y",
+            "h(n) = h(n - 1)",
+            "fib(a) = fib(a)",
+        ] {
+            assert!(
+                Parser::from_string(input.to_string())
+                    .parse(&eval_env)
+                    .is_err(),
+                "parsed {}, expected to fail",
+                input
+            );
+        }
+        end_test("clauses");
+    }
+    #[test]
+    fn test_overloading() {
+        start_test("overloading");
+        let (sink, mut eval_env) = collecting_env();
+        for def in [
+            // defined while log could still take any number of arguments
+            "one(x) = log(x)",
+            "log(b, x) = ln(x) / ln(b)",
+            "ln(x) = x - 1",
+            "area(r) = 3 * r^2",
+            "area(0, h) = 0",
+            "area(w, h) = w * h",
+            "area(a, b, c) = a + b + c",
+            "area(w, h) = 2 * w * h",
+            "sqrt(a, b) = a + b",
+        ] {
+            eval_on_string(def, &mut eval_env).expect(def);
+        }
+        assert_eq!(eval_env.arities("area"), ["1", "2", "3"]);
+        assert_eq!(eval_env.funcs["area"][&2].len(), 2);
+        for (input, expected) in [
+            ("log(2, 5)", "4"),
+            ("log(3, 10)", "4.5"),
+            ("ln(3)", "2"),
+            ("area(2)", "12"),
+            ("area(2, 3)", "12"),
+            ("area(0, 3)", "0"),
+            ("area(1, 2, 3)", "6"),
+            // the builtin still handles the arguments no definition takes
+            ("sqrt(9)", "3"),
+            ("sqrt(9, 1)", "10"),
+        ] {
+            let val = eval_on_string(input, &mut eval_env).expect(input);
+            assert_eq!(val.to_string(), expected, "evaluating {}", input);
+        }
+        // a function with several arities can't be passed around as a single lambda
+        assert_eq!(
+            eval_on_string("ln", &mut eval_env).map(|val| val.is_lambda()),
+            Some(true)
+        );
+        assert_eq!(
+            eval_on_string("area", &mut eval_env).map(|val| val.is_lambda()),
+            Some(false)
+        );
+        assert!(sink.take().is_empty());
+
+        for (input, expected) in [
+            (
+                "area(1, 2, 3, 4)",
+                "Call area(1,2,3,4) passes 4 arguments, but area is only defined for 1, 2 or 3.",
+            ),
+            (
+                "area(x, y, z, w)",
+                "Call area(x,y,z,w) passes 4 arguments, but area is only defined for 1, 2 or 3.",
+            ),
+            // calls that were parsed before the definitions are checked as well
+            (
+                "one(2)",
+                "Call log(2) passes 1 arguments, but log is only defined for 2.",
+            ),
+        ] {
+            assert!(
+                eval_on_string(input, &mut eval_env).is_none(),
+                "evaluated {}, expected to fail",
+                input
+            );
+            let diagnostics = sink.take();
+            assert_eq!(diagnostics.len(), 1);
+            assert_eq!(diagnostics[0].message, expected);
+        }
+        end_test("overloading");
+    }
+    #[test]
+    fn test_variadics_and_defaults() {
+        start_test("variadics and defaults");
+        let (sink, mut eval_env) = collecting_env();
+        for def in [
+            "scale(x, factor = 10) = x * factor",
+            "step = 2",
+            "count(from = 0, by = step) = from + by",
+            "f(a, rest...) = a + len(rest)",
+            "answer() = 42",
+            "first(0) = 0",
+            "first(x, others...) = x + len(others)",
+            "h(a, b = a) = a + b",
+            "shift(x, digits = 0) = x * 10^digits",
+        ] {
+            eval_on_string(def, &mut eval_env).expect(def);
+        }
+        // the variables defaults refer to are captured when the function is defined
+        eval_on_string("step = 5", &mut eval_env).unwrap();
+        for (input, expected) in [
+            ("mean(1, 2, 3, 4)", "2.5"),
+            ("mean(7)", "7"),
+            ("mean([1, 2, 6])", "3"),
+            ("sum([1, 2, 3])", "6"),
+            ("sum([])", "0"),
+            ("scale(2)", "20"),
+            ("scale(2, 3)", "6"),
+            ("scale(2, factor = 5)", "10"),
+            ("scale(factor = 5, x = 3)", "15"),
+            // the other arguments fill the parameters that are not passed by name
+            ("scale(1, x = 2)", "2"),
+            ("count()", "2"),
+            ("count(by = 1)", "1"),
+            ("count(3)", "5"),
+            ("f(1)", "1"),
+            ("f(1, 5, 6, 7)", "4"),
+            ("f(1, rest = [2, 3])", "3"),
+            ("answer() + 1", "43"),
+            // the clause taking exactly one argument is tried first
+            ("first(0)", "0"),
+            ("first(3)", "3"),
+            ("first(4, 5, 6)", "6"),
+            // a default is evaluated with the parameters before it
+            ("h(1)", "2"),
+            ("h(1, 5)", "6"),
+            ("shift(x = 2.5)", "2.5"),
+        ] {
+            let val = eval_on_string(input, &mut eval_env).expect(input);
+            assert_eq!(val.to_string(), expected, "evaluating {}", input);
+        }
+        assert_eq!(eval_env.arities("scale"), ["1 to 2"]);
+        assert_eq!(eval_env.arities("first"), ["1", "1 or more"]);
+        assert!(sink.take().is_empty());
+
+        for (input, expected) in [
+            (
+                "scale(1, 2, 3)",
+                "Call scale(1,2,3) passes 3 arguments, but scale is only defined for 1 to 2.",
+            ),
+            (
+                "scale()",
+                "Call scale() passes 0 arguments, but scale is only defined for 1 to 2.",
+            ),
+            (
+                "scale(1, digits = 2)",
+                "Call scale(1,digits=2) passes digits=2 by name, but scale has no parameter of that name.",
+            ),
+            (
+                "shift(digits = 2)",
+                "Call shift(digits=2) passes no value for parameter x of shift, which has no default.",
+            ),
+            (
+                "f(1, 2, rest = [3])",
+                "Call f(1,2,rest=[3]) passes 3 arguments, but f is only defined for 1 or more.",
+            ),
+            ("mean()", "Call mean() has no values to take the mean of."),
+            ("mean([])", "Call mean([]) has no values to take the mean of."),
+        ] {
+            assert!(
+                eval_on_string(input, &mut eval_env).is_none(),
+                "evaluated {}, expected to fail",
+                input
+            );
+            let diagnostics = sink.take();
+            assert_eq!(diagnostics.len(), 1);
+            assert_eq!(diagnostics[0].message, expected);
+        }
+
+        for input in [
+            "g(xs..., y) = y",
+            "g(a = 1, b) = b",
+            "g((a, b)...) = a",
+            "g(x...)",
+            "g(1...) = 1",
+            // a default can only refer to the parameters before it
+            "g(a, b = c) = a + b",
+            "g(a = b, b = 1) = a + b",
+        ] {
+            assert!(
+                Parser::from_string(input.to_string())
+                    .parse(&eval_env)
+                    .is_err(),
+                "parsed {}, expected to fail",
+                input
+            );
+        }
+        end_test("variadics and defaults");
+    }
 }